@@ -5,24 +5,86 @@ mod models {
     pub mod stack_config_json;
 }
 mod utils {
+    pub mod command_execution_util;
+    pub mod completion_util;
     pub mod configuration_util;
+    pub mod diagnostics_util;
+    pub mod dotenv_util;
+    pub mod environment_util;
     mod generic_utils;
+    pub mod interpolation_util;
+    pub mod json_source_util;
+    pub mod jsonc_util;
     pub mod ldot_stack_util;
+    pub mod migration_util;
+    pub mod output_util;
+    pub mod param_util;
+    pub mod schema_util;
+    pub mod stack_composition_util;
+    pub mod stack_format_util;
+    pub mod stack_listing_util;
+    pub mod stack_path_util;
+    pub mod stage_graph_util;
 }
 
-use std::{env, path::PathBuf};
+use std::path::PathBuf;
 
-use clap::Parser;
-use colored::Colorize;
+use clap::{CommandFactory, Parser};
 use models::{
-    command_line::{Cli, Commands, ConfigArgs},
+    command_line::{Cli, Commands, ConfigArgs, OutputFormat, SchemaKind},
     ldot_config_json::Configuration,
 };
 
-use crate::utils::{configuration_util, ldot_stack_util};
+use crate::utils::command_execution_util::{self, ExecutionReport};
+use crate::utils::stage_graph_util::ProjectSelector;
+use crate::utils::output_util::Verbosity;
+use crate::utils::{
+    completion_util, configuration_util, ldot_stack_util, output_util, schema_util, stack_format_util, stack_listing_util,
+};
 
 fn main() {
-    let cli = Cli::parse();
+    // "ldot __complete -- <words>" is called by the completion scripts on every <TAB>. It is not a subcommand so it
+    // stays out of the completions, and it must not print headers or repair the configuration.
+    let args: Vec<String> = std::env::args().collect();
+    if args.get(1).map(String::as_str) == Some(completion_util::COMPLETE_COMMAND) {
+        let words = args
+            .get(2..)
+            .unwrap_or_default()
+            .iter()
+            .skip_while(|word| *word != "--")
+            .skip(1);
+        for candidate in completion_util::complete(Cli::command(), &words.cloned().collect::<Vec<String>>()) {
+            println!("{}", candidate);
+        }
+        return;
+    }
+
+    // Invalid arguments exit with 1 like the other usage errors, clap would use 2 which means a broken stack file
+    let cli = Cli::try_parse().unwrap_or_else(|err| {
+        let _ = err.print();
+        std::process::exit(if err.use_stderr() { command_execution_util::EXIT_FAILURE } else { 0 });
+    });
+    output_util::set_color(cli.color);
+    output_util::set_verbosity(if cli.quiet {
+        Verbosity::Quiet
+    } else if cli.verbose {
+        Verbosity::Verbose
+    } else {
+        Verbosity::Normal
+    });
+
+    // Migrating reads the file as it is, the configuration it is given may be the one repaired below
+    if let Commands::Migrate(args) = &cli.command {
+        let filename = args.file.as_path().display().to_string();
+        output_util::print_header("Migrating Configuration File");
+        println!("Filename: {}", filename);
+        if let Err(err) = ldot_stack_util::migrate_file(&filename, args.dry_run, args.drop_comments) {
+            eprintln!("Migration failed: {}", err);
+            std::process::exit(command_execution_util::EXIT_FAILURE);
+        }
+        return;
+    }
+
     let mut configuration: Configuration;
 
     match configuration_util::get_configuration() {
@@ -30,10 +92,20 @@ fn main() {
             configuration = config;
         }
         Err(err) => {
-            println!("{}", "--- Startup ---".blue());
+            output_util::print_header("Startup");
             eprintln!("Could not fetch LDOT configuration: {}", err);
-            configuration = configuration_util::regenerate_configuration().unwrap();
-            println!("Regenerated configuration");
+            match configuration_util::recover_configuration() {
+                Ok((config, messages)) => {
+                    configuration = config;
+                    for message in messages {
+                        println!("{}", message);
+                    }
+                }
+                Err(err) => {
+                    eprintln!("Could not repair LDOT configuration: {}", err);
+                    std::process::exit(command_execution_util::EXIT_FAILURE);
+                }
+            }
         }
     }
 
@@ -51,47 +123,71 @@ fn main() {
     // Handle subcommands and arguments
     match cli.command {
         Commands::Validate(args) => {
-            let filename: PathBuf = args.file.unwrap().to_path_buf();
-            println!("{}", "--- Validating Configuration File ---".blue());
-            println!("Filename: {}", filename.to_string_lossy());
+            let filename: PathBuf = args.file.unwrap_or_else(stack_format_util::find_default_stack_file);
+            if args.format == OutputFormat::Text {
+                output_util::print_header("Validating Configuration File");
+                println!("Filename: {}", filename.to_string_lossy());
+            }
 
-            // Validation logic
-            match ldot_stack_util::validate_ldot_stack(filename.as_path().display().to_string()) {
-                Ok(_) => {
-                    println!("Validation succeeded.");
-                }
-                Err(err) => {
-                    eprintln!("Validation failed: {}", err);
+            // Validation logic, a stack that could not be parsed has no config to check
+            let (parsed, diagnostics) =
+                match ldot_stack_util::get_stack_diagnostics(&filename.as_path().display().to_string(), cli.profile) {
+                    Ok((stack_config, diagnostics)) => (stack_config.is_some(), diagnostics),
+                    Err(err) => {
+                        eprintln!("Validation failed: {}", err);
+                        std::process::exit(command_execution_util::EXIT_STACK_FILE_ERROR);
+                    }
+                };
+            match args.format {
+                OutputFormat::Json => println!("{}", diagnostics.to_json()),
+                OutputFormat::Text => {
+                    print!("{}", diagnostics.render());
+                    if diagnostics.has_errors() {
+                        eprintln!(
+                            "Validation failed: {} errors, {} warnings",
+                            diagnostics.error_count(),
+                            diagnostics.warning_count()
+                        );
+                    } else if diagnostics.warning_count() > 0 {
+                        println!("Validation succeeded with {} warnings.", diagnostics.warning_count());
+                    } else {
+                        println!("Validation succeeded.");
+                    }
                 }
             }
+            if !parsed {
+                std::process::exit(command_execution_util::EXIT_STACK_FILE_ERROR);
+            } else if diagnostics.has_errors() {
+                std::process::exit(command_execution_util::EXIT_FAILURE);
+            }
         }
-        Commands::Generate => {
-            println!("{}", "--- Generating Configuration File ---".blue());
-            let _ = ldot_stack_util::generate_ldot_stack();
+        Commands::Generate(args) => {
+            output_util::print_header("Generating Configuration File");
+            let _ = ldot_stack_util::generate_ldot_stack(args.format);
         }
         Commands::Load(args) => {
-            let filename: PathBuf = args.file.unwrap().to_path_buf();
-            println!("{}", "--- Loading Configuration File ---".blue());
+            let filename: PathBuf = args.file.unwrap_or_else(stack_format_util::find_default_stack_file);
+            output_util::print_header("Loading Configuration File");
             println!("Filename: {}", filename.to_string_lossy());
-            _ = ldot_stack_util::load_ldot_stack(filename.as_path().display().to_string());
+            ldot_stack_util::load_ldot_stack(filename.as_path().display().to_string());
             // Implement the loading logic
         }
         Commands::Unload(args) => {
-            let filename: PathBuf = args.file.unwrap().to_path_buf();
-            println!("{}", "--- Unloading Configuration File ---".blue());
+            let filename: PathBuf = args.file.unwrap_or_else(stack_format_util::find_default_stack_file);
+            output_util::print_header("Unloading Configuration File");
             println!("Filename: {}", filename.to_string_lossy());
-            _ = ldot_stack_util::unload_ldot_stack(filename.as_path().display().to_string());
+            ldot_stack_util::unload_ldot_stack(filename.as_path().display().to_string());
             // Implement the loading logic
         }
         Commands::Config(config_command) => match config_command.subcommand {
             ConfigArgs::List(_) => {
-                println!("{}", "--- Configuring LDOT ---".blue());
+                output_util::print_header("Configuring LDOT");
                 println!("Listing Config");
                 let _ = configuration_util::list_configurations();
             }
             ConfigArgs::Default(default_args) => {
-                println!("{}", "--- Configuring LDOT ---".blue());
-                if let Some(stack) = default_args.stack {
+                output_util::print_header("Configuring LDOT");
+                if let Some(stack) = default_args.stack_name {
                     match configuration_util::set_default_stack(&stack.to_string_lossy()) {
                         Ok(_) => {
                             println!("Set default stack to: {}", stack.to_string_lossy());
@@ -108,87 +204,68 @@ fn main() {
                 }
             }
             ConfigArgs::Regenerate(_) => {
-                println!("{}", "--- Configuring LDOT ---".blue());
+                output_util::print_header("Configuring LDOT");
                 println!("Regenerating the LDOT config file");
                 let _ = configuration_util::regenerate_configuration();
             }
-        },
-        Commands::Execute(_execute_args) => {
-            let args: Vec<String> = env::args().collect();
-            println!("{}", "--- Executing Stack Commands ---".blue());
-            // Handle the case where stack/project/stage names are provided
-            if args.len() == 4 && configuration.default_stack != "" {
-                let project_name = args.get(2);
-                let stage_name = args.get(3);
-
-                println!("Stack: {}", configuration.default_stack);
-                if let Some(proj) = project_name {
-                    println!("Project: {}", proj);
-                }
-                if let Some(stage) = stage_name {
-                    println!("Stage: {}", stage);
-                }
-
-                match ldot_stack_util::execute_stack_stage_script(
-                    configuration.default_stack,
-                    project_name.unwrap().to_string(),
-                    stage_name.unwrap().to_string(),
-                ) {
-                    Ok(cmds) => {
-                        println!("Commands Executed:");
-                        for cmd in &cmds {
-                            println!("    {}", cmd);
-                        }
-                    }
-                    Err(err) => {
-                        println!("Commands Executed (Errors Occured):");
-                        for err in &err {
-                            println!("    {}", err);
+            ConfigArgs::Profile(profile_args) => {
+                output_util::print_header("Configuring LDOT");
+                let stack = get_stack_name(cli.stack, &configuration);
+                if profile_args.name.is_none() && !profile_args.clear {
+                    println!(
+                        "Profile of {} is: {}\nConfiguration file remains unchanged.",
+                        stack,
+                        configuration.active_profiles.get(&stack).map(String::as_str).unwrap_or("none")
+                    );
+                } else {
+                    match configuration_util::set_active_profile(&stack, profile_args.name.as_deref()) {
+                        Ok(_) => match profile_args.name {
+                            Some(name) => println!("Set profile of {} to: {}", stack, name),
+                            None => println!("Cleared profile of: {}", stack),
+                        },
+                        Err(err) => {
+                            eprintln!("{}", err);
+                            std::process::exit(command_execution_util::EXIT_FAILURE);
                         }
                     }
                 }
-                // Implement logic to execute commands based on provided names
-            } else if args.len() == 5 {
-                let stack_name = args.get(2);
-                let project_name = args.get(3);
-                let stage_name = args.get(4);
-
-                if let Some(stack) = stack_name {
-                    println!("Stack: {}", stack);
-                }
-                if let Some(proj) = project_name {
-                    println!("Project: {}", proj);
-                }
-                if let Some(stage) = stage_name {
-                    println!("Stage: {}", stage);
-                }
+            }
+        },
+        Commands::Execute(execute_args) => {
+            output_util::print_header("Executing Stack Commands");
+            let stack_name = get_stack_name(cli.stack, &configuration);
+            let mut stages = execute_args.stages;
+            // --all and --tag select the projects instead of a project name, every argument is a stage
+            let selector = if execute_args.all {
+                stages.insert(0, execute_args.project);
+                ProjectSelector::All
+            } else if !execute_args.tag.is_empty() {
+                stages.insert(0, execute_args.project);
+                ProjectSelector::Tags(execute_args.tag)
+            } else {
+                ProjectSelector::Name(execute_args.project)
+            };
 
-                match ldot_stack_util::execute_stack_stage_script(
-                    stack_name.unwrap().to_string(),
-                    project_name.unwrap().to_string(),
-                    stage_name.unwrap().to_string(),
-                ) {
-                    Ok(cmds) => {
-                        println!("\nCommands Executed:");
-                        for cmd in &cmds {
-                            println!("    {}", cmd);
-                        }
-                    }
-                    Err(err) => {
-                        println!("\nCommands Executed (Errors Occured):");
-                        for err in &err {
-                            println!("    {}", err);
-                        }
-                    }
-                }
-                // Implement logic to execute commands based on provided names
+            output_util::print_info(format!("Stack: {}", stack_name));
+            if selector.is_single_project() {
+                output_util::print_info(format!("Project: {}", selector));
             } else {
-                println!("{}Project and Stage is required", if configuration.default_stack == "" {"Stack, "} else {""})
+                output_util::print_info(format!("Projects: {}", selector));
             }
-            // Implement the generation logic
+            output_util::print_info(format!("{}: {}", if stages.len() == 1 { "Stage" } else { "Stages" }, stages.join(", ")));
+
+            finish_execution(ldot_stack_util::execute_stack_stage_script(
+                stack_name,
+                selector,
+                stages,
+                execute_args.args,
+                execute_args.jobs,
+                execute_args.keep_going,
+                cli.profile,
+            ));
         }
         Commands::Version => {
-            println!("{}", "--- LDOT Build Details ---".blue());
+            output_util::print_header("LDOT Build Details");
             println!("Program Name: {}", env!("CARGO_PKG_NAME"));
             println!("Description: {}", env!("CARGO_PKG_DESCRIPTION"));
             println!("Author: {}", env!("CARGO_PKG_AUTHORS"));
@@ -197,65 +274,172 @@ fn main() {
             //println!("Readme: {}", env!("CARGO_PKG_README"));
             println!("Version: {}", env!("CARGO_PKG_VERSION"));
         }
-        Commands::Script(_script_args) => {
-            println!("{}", "--- Executing Script ---".blue());
-            let args: Vec<String> = env::args().collect();
+        Commands::Script(script_args) => {
+            output_util::print_header("Executing Script");
+            let stack_name = get_stack_name(cli.stack, &configuration);
+            output_util::print_info(format!("Stack: {}", stack_name));
+            output_util::print_info(format!("Script: {}", script_args.script));
 
-            if args.len() == 3 && configuration.default_stack != "" {
-                let script_name = args.get(2);
-
-                if let Some(script) = script_name {
-                    println!("Script: {}", script);
+            finish_execution(ldot_stack_util::execute_stack_script(
+                stack_name,
+                script_args.script,
+                script_args.args,
+                script_args.params,
+                script_args.keep_going,
+                cli.profile,
+            ));
+        }
+        Commands::Schema(schema_args) => {
+            // Printed without a header so the output can be redirected into a file
+            match schema_args.kind {
+                SchemaKind::Stack => println!("{}", schema_util::get_stack_schema()),
+                SchemaKind::Partial => println!("{}", schema_util::get_partial_stack_schema()),
+                SchemaKind::Config => println!("{}", schema_util::get_configuration_schema()),
+            }
+        }
+        Commands::AddProject(args) => {
+            let filename: PathBuf = args.file.unwrap_or_else(stack_format_util::find_default_stack_file);
+            output_util::print_header("Adding Project");
+            println!("Filename: {}", filename.to_string_lossy());
+            match ldot_stack_util::add_project(
+                &filename.as_path().display().to_string(),
+                args.name,
+                args.description,
+                args.path,
+                args.drop_comments,
+            ) {
+                Ok(_) => println!("Project added."),
+                Err(err) => {
+                    eprintln!("Could not add project: {}", err);
+                    std::process::exit(command_execution_util::EXIT_FAILURE);
                 }
-
-                match ldot_stack_util::execute_stack_script(
-                    configuration.default_stack,
-                    script_name.unwrap().to_string()
-                ) {
-                    Ok(cmds) => {
-                        println!("\nCommands Executed:");
-                        for cmd in &cmds {
-                            println!("    {}", cmd);
-                        }
+            }
+        }
+        Commands::Fmt(args) => {
+            let files: Vec<PathBuf> = if args.files.is_empty() {
+                vec![stack_format_util::find_default_stack_file()]
+            } else {
+                args.files
+            };
+            output_util::print_header("Formatting Configuration Files");
+            let mut exit_code = 0;
+            for filename in files {
+                let filename = filename.as_path().display().to_string();
+                match ldot_stack_util::format_stack_file(&filename, args.check, args.omit_defaults, args.drop_comments) {
+                    Ok(false) => println!("Already formatted: {}", filename),
+                    Ok(true) if args.check => {
+                        println!("Not formatted: {}", filename);
+                        exit_code = exit_code.max(command_execution_util::EXIT_FAILURE);
                     }
+                    Ok(true) => println!("Formatted: {}", filename),
                     Err(err) => {
-                        println!("\nCommands Executed (Errors Occured):");
-                        for err in &err {
-                            println!("    {}", err);
-                        }
+                        eprintln!("{}", err);
+                        exit_code = command_execution_util::EXIT_STACK_FILE_ERROR;
                     }
                 }
-            } else if args.len() == 4 {
-                let stack_name = args.get(2);
-                let script_name = args.get(3);
-
-                if let Some(script) = script_name {
-                    println!("Script: {}", script);
+            }
+            std::process::exit(exit_code);
+        }
+        Commands::Migrate(_) => {
+            // handled before the configuration is read
+        }
+        Commands::List(args) => {
+            // Printed without a header so the output can be used in scripts
+            let entries = match stack_listing_util::list(args.kind, cli.stack.as_deref(), cli.profile) {
+                Ok(entries) => entries,
+                Err(err) => {
+                    eprintln!("{}", err);
+                    std::process::exit(command_execution_util::EXIT_FAILURE);
                 }
-                if let Some(stack) = stack_name {
-                    println!("Stack: {}", stack);
+            };
+            match args.format {
+                OutputFormat::Json => {
+                    let details: Vec<&serde_json::Value> = entries.iter().map(|entry| &entry.details).collect();
+                    println!("{}", serde_json::to_string_pretty(&details).unwrap());
                 }
+                OutputFormat::Text => print!("{}", stack_listing_util::render_list(&entries)),
+            }
+        }
+        Commands::Show(args) => {
+            let target = args.target.unwrap_or_else(|| get_stack_name(cli.stack, &configuration));
+            let target = match stack_listing_util::parse_show_target(&target) {
+                Ok(target) => target,
+                Err(err) => {
+                    eprintln!("{}", err);
+                    std::process::exit(command_execution_util::EXIT_FAILURE);
+                }
+            };
 
-                match ldot_stack_util::execute_stack_script(
-                    stack_name.unwrap().to_string(),
-                    script_name.unwrap().to_string()
-                ) {
-                    Ok(cmds) => {
-                        println!("\nCommands Executed:");
-                        for cmd in &cmds {
-                            println!("    {}", cmd);
-                        }
-                    }
-                    Err(err) => {
-                        println!("\nCommands Executed (Errors Occured):");
-                        for err in &err {
-                            println!("    {}", err);
-                        }
+            if (args.source || args.resolved) && target.project.is_some() {
+                eprintln!("--source and --resolved print a whole stack file, leave out the project and stage");
+                std::process::exit(command_execution_util::EXIT_FAILURE);
+            }
+
+            // Printed without a header so the output can be redirected into a file
+            let output = if args.source || args.resolved {
+                ldot_stack_util::show_stack(target.stack, args.resolved, cli.profile)
+            } else {
+                stack_listing_util::show(&target, cli.profile).map(|value| match args.format {
+                    OutputFormat::Json => format!("{}\n", serde_json::to_string_pretty(&value).unwrap()),
+                    OutputFormat::Text => stack_listing_util::render_tree(&value),
+                })
+            };
+            match output {
+                Ok(output) => print!("{}", output),
+                Err(err) => {
+                    eprintln!("{}", err);
+                    std::process::exit(command_execution_util::EXIT_FAILURE);
+                }
+            }
+        }
+        Commands::Completions(args) => {
+            clap_complete::generate(args.shell.to_clap_shell(), &mut Cli::command(), "ldot", &mut std::io::stdout());
+            print!("{}", completion_util::get_dynamic_completion_script(args.shell));
+        }
+        Commands::Env(env_args) => {
+            let stack_name = get_stack_name(cli.stack, &configuration);
+
+            // Printed without a header so the output can be redirected into a .env file
+            match ldot_stack_util::get_stack_environment(stack_name, env_args.project, env_args.stage, cli.profile) {
+                Ok(lines) => {
+                    for line in lines {
+                        println!("{}", line);
                     }
                 }
-            } else {
-                println!("Script {} name is required", if configuration.default_stack == "" {"and Stack"} else {""})
+                Err(err) => {
+                    eprintln!("{}", err);
+                    std::process::exit(command_execution_util::EXIT_FAILURE);
+                }
             }
         }
     }
 }
+
+// The stack given with --stack, or the default stack. Exits when neither is set.
+fn get_stack_name(stack: Option<String>, configuration: &Configuration) -> String {
+    match stack {
+        Some(stack) => stack,
+        None if !configuration.default_stack.is_empty() => configuration.default_stack.to_string(),
+        None => {
+            eprintln!("Stack is required, use --stack or set a default stack with \"ldot config default\"");
+            std::process::exit(command_execution_util::EXIT_FAILURE);
+        }
+    }
+}
+
+// Prints the "Commands Executed" summary and exits with the code of the first failing command
+// With --quiet, the summary is only printed when a command failed.
+fn finish_execution(report: ExecutionReport) {
+    if report.succeeded() && output_util::is_quiet() {
+        std::process::exit(report.exit_code);
+    }
+    if report.succeeded() {
+        println!("\nCommands Executed:");
+    } else {
+        println!("\nCommands Executed (Errors Occured):");
+    }
+    for cmd in &report.commands {
+        println!("    {}", cmd);
+    }
+    std::process::exit(report.exit_code);
+}
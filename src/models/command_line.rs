@@ -1,13 +1,33 @@
 use std::ffi::OsString;
 use std::path::PathBuf;
 
-use clap::{command, Args, Parser, Subcommand};
+use clap::{Args, Parser, Subcommand, ValueEnum};
+
+use crate::utils::completion_util::CompletionShell;
+use crate::utils::output_util::ColorChoice;
+use crate::utils::stack_format_util::StackFormat;
+use crate::utils::stack_listing_util::ListKind;
 
 #[derive(Debug, Parser)]
 #[command(name = "LDOT", about = "A Local Development Orchestration Tool", long_about = None, author = "UndyingSoul", version = env!("CARGO_PKG_VERSION"), disable_help_flag = true, disable_version_flag = true)]
 pub struct Cli {
     #[command(subcommand)]
     pub command: Commands,
+
+    #[arg(long, global = true, help = "Stack name, defaults to the default stack")]
+    pub stack: Option<String>,
+
+    #[arg(long, global = true, help = "Profile of the stack to use, instead of the one set with \"ldot config profile\"")]
+    pub profile: Option<String>,
+
+    #[arg(short, long, global = true, help = "Print the stack file, the execution order and where each command runs")]
+    pub verbose: bool,
+
+    #[arg(short, long, global = true, conflicts_with = "verbose", help = "Only print the output of commands and errors")]
+    pub quiet: bool,
+
+    #[arg(long, global = true, value_enum, value_name = "WHEN", default_value_t = ColorChoice::Auto, help = "When to color the output")]
+    pub color: ColorChoice,
 }
 
 // Define the structure for command-line parameters
@@ -26,7 +46,7 @@ pub enum Commands {
         about = "Generates an LDOT file",
         long_about = "Generates an LDOT file to be changed manually."
     )]
-    Generate,
+    Generate(GenerateArgs),
 
     #[command(
         about = "Loads an LDOT file",
@@ -48,6 +68,48 @@ pub enum Commands {
 
     #[command(about = "Executes an LDOT script command")]
     Script(ScriptArgs),
+
+    #[command(about = "Prints the resolved environment of an LDOT project or stage")]
+    Env(EnvArgs),
+
+    #[command(about = "Prints the JSON Schema of LDOT stack files or of the LDOT configuration")]
+    Schema(SchemaArgs),
+
+    #[command(
+        about = "Adds a project to an LDOT file",
+        long_about = "Adds an empty project to an LDOT file. Comments and formatting of JSON files are kept."
+    )]
+    AddProject(AddProjectArgs),
+
+    #[command(
+        about = "Formats LDOT files",
+        long_about = "Rewrites LDOT files with canonical key order and indentation. Comments of JSON files are kept."
+    )]
+    Fmt(FmtArgs),
+
+    #[command(
+        about = "Migrates a legacy LDOT file",
+        long_about = "Converts a stack or configuration file written for older versions of LDOT (stackName, defaultStack, projects keyed by name) to the current format."
+    )]
+    Migrate(MigrateArgs),
+
+    #[command(
+        about = "Lists LDOT stacks, projects, stages or scripts",
+        long_about = "Lists the registered LDOT stacks, or the projects, stages or scripts of every registered stack or of --stack, with their descriptions."
+    )]
+    List(ListStacksArgs),
+
+    #[command(
+        about = "Prints an LDOT stack, project or stage",
+        long_about = "Prints the projects, stages and scripts of a registered LDOT stack as a tree with their descriptions, prerequisites and commands. With --source, prints the stack file as written, or with --resolved after merging what it extends, includes and its local overrides."
+    )]
+    Show(ShowArgs),
+
+    #[command(
+        about = "Prints a shell completion script",
+        long_about = "Prints a completion script for bash, zsh, fish or elvish. Besides subcommands and flags it completes the stacks, projects, stages, scripts and profiles of the registered stack files."
+    )]
+    Completions(CompletionsArgs),
 }
 
 #[derive(Debug, Args)]
@@ -58,18 +120,33 @@ pub struct ConfigSubcommand {
 
 #[derive(Debug, Args)]
 pub struct ValidateArgs {
-    #[arg(value_name = "FILE", default_value = "ldot_stack.json")]
+    #[arg(value_name = "FILE", help = "Defaults to ldot_stack.json, .yaml, .yml or .toml in the current directory")]
     pub file: Option<PathBuf>,
+
+    #[arg(long, value_enum, default_value_t = OutputFormat::Text, help = "Output format, json is meant for editors and scripts")]
+    pub format: OutputFormat,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    Text,
+    Json,
+}
+
+#[derive(Debug, Args)]
+pub struct GenerateArgs {
+    #[arg(long, value_enum, default_value_t = StackFormat::Json, help = "Format of the generated stack file")]
+    pub format: StackFormat,
 }
 
 #[derive(Debug, Args)]
 pub struct LoadArgs {
-    #[arg(value_name = "FILE", default_value = "ldot_stack.json")]
+    #[arg(value_name = "FILE", help = "Defaults to ldot_stack.json, .yaml, .yml or .toml in the current directory")]
     pub file: Option<PathBuf>,
 }
 #[derive(Debug, Args)]
 pub struct UnloadArgs {
-    #[arg(value_name = "FILE", default_value = "ldot_stack.json")]
+    #[arg(value_name = "FILE", help = "Defaults to ldot_stack.json, .yaml, .yml or .toml in the current directory")]
     pub file: Option<PathBuf>,
 }
 
@@ -86,6 +163,9 @@ pub enum ConfigArgs {
 
     #[command(about = "Regenerates LDOT config file", alias = "reset")]
     Regenerate(RegenerateArgs),
+
+    #[command(about = "Sets the profile used for an LDOT stack")]
+    Profile(ProfileArgs),
 }
 
 #[derive(Debug, Args)]
@@ -93,8 +173,17 @@ pub struct ListArgs;
 
 #[derive(Debug, Args)]
 pub struct DefaultArgs {
-    #[arg(help = "Stack name")]
-    pub stack: Option<OsString>,
+    #[arg(value_name = "STACK", help = "Stack name")]
+    pub stack_name: Option<OsString>,
+}
+
+#[derive(Debug, Args)]
+pub struct ProfileArgs {
+    #[arg(value_name = "PROFILE", help = "Profile name")]
+    pub name: Option<String>,
+
+    #[arg(long, conflicts_with = "name", help = "Stop using a profile for the stack")]
+    pub clear: bool,
 }
 
 // #[derive(Debug, Args)]
@@ -105,10 +194,136 @@ pub struct RegenerateArgs;
 
 #[derive(Debug, Args)]
 pub struct ExecuteArgs {
-    args: Vec<OsString>, // Define fields for executing here
+    #[arg(short, long, value_name = "N", help = "Number of independent stages to run in parallel, overrides the stack's max_parallel")]
+    pub jobs: Option<usize>,
+
+    #[arg(short, long, help = "Keep running commands and independent stages after a failure")]
+    pub keep_going: bool,
+
+    #[arg(long, help = "Run the stage in every project that has it, instead of naming a project")]
+    pub all: bool,
+
+    #[arg(long, value_name = "TAG", conflicts_with = "all", help = "Run the stage in every project with this tag that has it, can be repeated")]
+    pub tag: Vec<String>,
+
+    #[arg(value_name = "PROJECT", help = "Project name or a pattern such as \"svc-*\". With --all or --tag, the first stage instead")]
+    pub project: String,
+
+    #[arg(value_name = "STAGE", required_unless_present_any = ["all", "tag"], help = "Stages to run, prerequisites they share run once")]
+    pub stages: Vec<String>,
+
+    #[arg(last = true, value_name = "ARGS", help = "Arguments after \"--\" are forwarded to the stages named here, as ${args} or $@")]
+    pub args: Vec<String>,
 }
 
 #[derive(Debug, Args)]
 pub struct ScriptArgs {
-    args: Vec<OsString>, // Define fields for executing here
+    #[arg(short, long, help = "Keep running the remaining commands after a failure")]
+    pub keep_going: bool,
+
+    #[arg(value_name = "SCRIPT", help = "Script name")]
+    pub script: String,
+
+    #[arg(long = "param", value_name = "NAME=VALUE", help = "Value of one of the script's params, can be repeated")]
+    pub params: Vec<String>,
+
+    #[arg(last = true, value_name = "ARGS", help = "Arguments after \"--\" are forwarded to the script as ${args} or $@")]
+    pub args: Vec<String>,
+}
+
+#[derive(Debug, Args)]
+pub struct EnvArgs {
+    #[arg(short, long, help = "Include the stage's env and env_files")]
+    pub stage: Option<String>,
+
+    #[arg(value_name = "PROJECT", help = "Project name")]
+    pub project: String,
+}
+
+#[derive(Debug, Args)]
+pub struct SchemaArgs {
+    #[arg(value_enum, default_value_t = SchemaKind::Stack)]
+    pub kind: SchemaKind,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum SchemaKind {
+    Stack,
+    // Local overrides, which may leave out every key
+    Partial,
+    Config,
+}
+
+#[derive(Debug, Args)]
+pub struct AddProjectArgs {
+    pub name: String,
+
+    #[arg(short, long, help = "Description of the project")]
+    pub description: Option<String>,
+
+    #[arg(short, long, help = "Directory of the project, relative to the stack root")]
+    pub path: Option<String>,
+
+    #[arg(short, long, value_name = "FILE", help = "Defaults to ldot_stack.json, .yaml, .yml or .toml in the current directory")]
+    pub file: Option<PathBuf>,
+
+    #[arg(long, help = "Rewrite YAML and TOML files even though their comments are removed")]
+    pub drop_comments: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct FmtArgs {
+    #[arg(long, help = "Exit with 1 instead of writing when a file is not formatted")]
+    pub check: bool,
+
+    #[arg(long, help = "Leave out optional values that are equal to their defaults")]
+    pub omit_defaults: bool,
+
+    #[arg(long, help = "Rewrite YAML and TOML files even though their comments are removed")]
+    pub drop_comments: bool,
+
+    #[arg(value_name = "FILE", help = "Defaults to ldot_stack.json, .yaml, .yml or .toml in the current directory")]
+    pub files: Vec<PathBuf>,
+}
+
+#[derive(Debug, Args)]
+pub struct MigrateArgs {
+    #[arg(long, help = "Print the changes as a diff without writing the file")]
+    pub dry_run: bool,
+
+    #[arg(long, help = "Rewrite YAML and TOML files even though their comments are removed")]
+    pub drop_comments: bool,
+
+    #[arg(value_name = "FILE")]
+    pub file: PathBuf,
+}
+
+#[derive(Debug, Args)]
+pub struct ListStacksArgs {
+    #[arg(value_enum, default_value_t = ListKind::Stacks)]
+    pub kind: ListKind,
+
+    #[arg(long, value_enum, default_value_t = OutputFormat::Text, help = "Output format, json is meant for editors and scripts")]
+    pub format: OutputFormat,
+}
+
+#[derive(Debug, Args)]
+pub struct ShowArgs {
+    #[arg(long, conflicts_with = "format", help = "Print the stack file as written")]
+    pub source: bool,
+
+    #[arg(long, conflicts_with = "format", help = "Print the stack file after merging extended stacks, included projects and the local override file")]
+    pub resolved: bool,
+
+    #[arg(long, value_enum, default_value_t = OutputFormat::Text, help = "Output format, json is meant for editors and scripts")]
+    pub format: OutputFormat,
+
+    #[arg(value_name = "STACK[/PROJECT[/STAGE]]", help = "Defaults to --stack or the default stack")]
+    pub target: Option<String>,
+}
+
+#[derive(Debug, Args)]
+pub struct CompletionsArgs {
+    #[arg(value_enum, value_name = "SHELL")]
+    pub shell: CompletionShell,
 }
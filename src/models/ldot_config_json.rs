@@ -1,7 +1,12 @@
+use std::collections::BTreeMap;
+
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
 pub struct Configuration {
     pub default_stack: String,
     pub registered_stack_files: Vec<String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")] // Make active_profiles field optional, the profile of each stack by stack name
+    pub active_profiles: BTreeMap<String, String>,
 }
\ No newline at end of file
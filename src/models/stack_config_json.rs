@@ -1,41 +1,206 @@
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize, ser::SerializeStruct};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::fmt;
 
-#[derive(Deserialize)]
+use crate::utils::stack_format_util;
+
+#[derive(Deserialize, Clone, JsonSchema)]
 pub struct StackConfig {
+    #[serde(rename = "$schema", default)] // Make $schema field optional, points editors at the stack file's JSON Schema
+    pub schema: Option<String>,
+    #[serde(default)] // Make schema_version field optional, files without it use schema version 1
+    pub schema_version: Option<u64>,
+    #[serde(default)] // Make min_ldot_version field optional, e.g. "1.2.0" for stacks that need newer features
+    pub min_ldot_version: Option<String>,
+    #[serde(default)] // Make extends field optional, a base stack file whose content this stack overrides
+    pub extends: Option<String>,
+    #[serde(default)] // Make include field optional, project files that are added to the stack
+    pub include: Vec<String>,
     #[serde(default)] // Make version field optional and default to an empty string
     pub version: String,
     pub stack_name: String,
     #[serde(default)] // Make description field optional and default to an empty string
     pub description: String,
+    #[serde(default)] // Make max_parallel field optional, stages run one at a time unless set
+    pub max_parallel: Option<usize>,
+    #[serde(default)] // Make root field optional, relative to the stack file's directory
+    pub root: Option<String>,
+    #[serde(default)] // Make variables field optional and default to an empty map
+    pub variables: BTreeMap<String, String>,
+    #[serde(default)] // Make env field optional and default to an empty map
+    pub env: BTreeMap<String, String>,
+    #[serde(default)] // Make env_files field optional and default to an empty array
+    pub env_files: Vec<String>,
+    #[serde(default)] // Make shell field optional, commands run through "sh -c" ("cmd /C" on Windows) unless set
+    pub shell: Option<String>,
+    #[serde(default)] // Make exec field optional, when true commands are tokenized and run without a shell
+    pub exec: Option<bool>,
     pub projects: Vec<ProjectConfig>,
+    #[serde(default)] // Make groups field optional, e.g. { "all": ["*"] } runs a stage in every project with "ldot execute all <stage>"
+    pub groups: BTreeMap<String, Vec<String>>,
     pub scripts: Vec<ScriptConfig>,
+    #[serde(default)] // Make profiles field optional and default to an empty array
+    pub profiles: Vec<ProfileConfig>,
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, Clone, JsonSchema)]
 pub struct ProjectConfig {
     pub project_name: String,
     #[serde(default)] // Make project_description field optional and default to an empty string
     pub project_description: String,
+    #[serde(default)] // Make path field optional, relative to the stack root
+    pub path: Option<String>,
+    #[serde(default = "default_enabled")] // Make enabled field optional, disabled projects are left out of the stack
+    pub enabled: bool,
+    #[serde(default)] // Make tags field optional and default to an empty array, e.g. ["backend"] for "ldot execute --tag backend"
+    pub tags: Vec<String>,
+    #[serde(default)] // Make env field optional and default to an empty map
+    pub env: BTreeMap<String, String>,
+    #[serde(default)] // Make env_files field optional and default to an empty array
+    pub env_files: Vec<String>,
     pub stages: Vec<StageConfig>,
+    #[serde(skip)] // Set on the projects groups are expanded to, never read from a stack file
+    pub group: bool,
+}
+
+fn default_enabled() -> bool {
+    true
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, Clone, JsonSchema)]
 pub struct StageConfig {
     pub stage_name: String,
+    #[serde(default)] // Make aliases field optional, other names the stage can be executed and referenced by
+    pub aliases: Vec<String>,
     #[serde(default)] // Make stage_description field optional and default to an empty string
     pub stage_description: String,
     #[serde(default)] // Make prerequisites field optional and default to an empty array
     pub prerequisites: Vec<String>,
     #[serde(default)] // Make commands field optional and default to an empty array
-    pub commands: Vec<String>,
+    pub commands: Vec<CommandConfig>,
+    #[serde(default)] // Make shell field optional, falls back to the stack's shell
+    pub shell: Option<String>,
+    #[serde(default)] // Make exec field optional, falls back to the stack's exec
+    pub exec: Option<bool>,
+    #[serde(default)] // Make continue_on_error field optional, a failing command stops the run unless set
+    pub continue_on_error: bool,
+    #[serde(default)] // Make env field optional and default to an empty map
+    pub env: BTreeMap<String, String>,
+    #[serde(default)] // Make env_files field optional and default to an empty array
+    pub env_files: Vec<String>,
 }
-#[derive(Deserialize)]
+#[derive(Deserialize, Clone, JsonSchema)]
 pub struct ScriptConfig {
     pub script_name: String,
     #[serde(default)] // Make project_description field optional and default to an empty string
     pub script_description: String,
-    pub commands: Vec<String>,
+    pub commands: Vec<CommandConfig>,
+    #[serde(default)] // Make shell field optional, falls back to the stack's shell
+    pub shell: Option<String>,
+    #[serde(default)] // Make exec field optional, falls back to the stack's exec
+    pub exec: Option<bool>,
+    #[serde(default)] // Make env field optional and default to an empty map
+    pub env: BTreeMap<String, String>,
+    #[serde(default)] // Make env_files field optional and default to an empty array
+    pub env_files: Vec<String>,
+    #[serde(default)] // Make params field optional and default to an empty array
+    pub params: Vec<ParamConfig>,
+}
+
+// A named parameter of a script, given on the command line as "--param name=value" and used as "${params.name}"
+#[derive(Deserialize, Clone, JsonSchema)]
+pub struct ParamConfig {
+    pub param_name: String,
+    #[serde(default)] // Make param_description field optional and default to an empty string
+    pub param_description: String,
+    #[serde(default)] // Make param_type field optional and default to a string
+    pub param_type: ParamType,
+    #[serde(default)] // Make default field optional, a parameter without a default is required
+    pub default: Option<Value>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum ParamType {
+    #[default]
+    String,
+    Number,
+    Boolean,
 }
+
+impl fmt::Display for ParamType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParamType::String => write!(f, "string"),
+            ParamType::Number => write!(f, "number"),
+            ParamType::Boolean => write!(f, "boolean"),
+        }
+    }
+}
+
+// Variables and env for one way of running the stack, e.g. against a local or a seeded test database
+#[derive(Deserialize, Clone, JsonSchema)]
+pub struct ProfileConfig {
+    pub profile_name: String,
+    #[serde(default)] // Make profile_description field optional and default to an empty string
+    pub profile_description: String,
+    #[serde(default)] // Make variables field optional and default to an empty map
+    pub variables: BTreeMap<String, String>,
+    #[serde(default)] // Make env field optional and default to an empty map
+    pub env: BTreeMap<String, String>,
+    #[serde(default)] // Make projects field optional, when set only these projects are enabled
+    pub projects: Option<Vec<String>>,
+}
+
+// A command is either a plain string or an object with extra execution settings
+#[derive(Deserialize, Clone, JsonSchema)]
+#[serde(untagged)]
+pub enum CommandConfig {
+    Simple(String),
+    Detailed(CommandDetails),
+}
+
+#[derive(Deserialize, Clone, Default, JsonSchema)]
+pub struct CommandDetails {
+    pub run: String,
+    #[serde(default)] // Make cwd field optional, relative to the project directory (the stack root for scripts)
+    pub cwd: Option<String>,
+    #[serde(default)] // Make env field optional and default to an empty map
+    pub env: BTreeMap<String, String>,
+    #[serde(default)] // Make timeout field optional, e.g. "500ms", "90s", "5m" or "1h"
+    pub timeout: Option<String>,
+    #[serde(default)] // Make ignore_errors field optional and default to false
+    pub ignore_errors: bool,
+    #[serde(default)] // Make retries field optional and default to no retries
+    pub retries: u32,
+}
+
+impl CommandConfig {
+    // Plain strings are treated as an object with only "run" set
+    pub fn to_details(&self) -> CommandDetails {
+        match self {
+            CommandConfig::Simple(run) => CommandDetails {
+                run: run.to_string(),
+                ..Default::default()
+            },
+            CommandConfig::Detailed(details) => details.clone(),
+        }
+    }
+
+    // The same command with another "run", keeping the plain string form when there are no other settings
+    pub fn with_run(&self, run: String) -> CommandConfig {
+        match self {
+            CommandConfig::Simple(_) => CommandConfig::Simple(run),
+            CommandConfig::Detailed(details) => CommandConfig::Detailed(CommandDetails {
+                run,
+                ..details.clone()
+            }),
+        }
+    }
+}
+
 // // Deserialize JSON string into StackConfig
 // pub fn deserialize_stack_config(json_str: &str) -> Result<StackConfig, serde_json::Error> {
 //     serde_json::from_str(json_str)
@@ -80,13 +245,76 @@ impl Serialize for StackConfig {
     where
         S: serde::Serializer,
     {
-        let mut state = serializer.serialize_struct("StackConfig", 5)?;
+        let mut state = serializer.serialize_struct("StackConfig", 19)?;
+
+        match &self.schema {
+            Some(_) => state.serialize_field("$schema", &self.schema)?,
+            None => state.skip_field("$schema")?,
+        }
+        match self.schema_version {
+            Some(_) => state.serialize_field("schema_version", &self.schema_version)?,
+            None => state.skip_field("schema_version")?,
+        }
+        match &self.min_ldot_version {
+            Some(_) => state.serialize_field("min_ldot_version", &self.min_ldot_version)?,
+            None => state.skip_field("min_ldot_version")?,
+        }
+        match &self.extends {
+            Some(_) => state.serialize_field("extends", &self.extends)?,
+            None => state.skip_field("extends")?,
+        }
+        if self.include.is_empty() {
+            state.skip_field("include")?;
+        } else {
+            state.serialize_field("include", &self.include)?;
+        }
 
         state.serialize_field("version", &self.version)?;
         state.serialize_field("stack_name", &self.stack_name)?;
         state.serialize_field("description", &self.description)?;
+        match self.max_parallel {
+            Some(_) => state.serialize_field("max_parallel", &self.max_parallel)?,
+            None => state.skip_field("max_parallel")?,
+        }
+        match &self.root {
+            Some(_) => state.serialize_field("root", &self.root)?,
+            None => state.skip_field("root")?,
+        }
+        if self.variables.is_empty() {
+            state.skip_field("variables")?;
+        } else {
+            state.serialize_field("variables", &self.variables)?;
+        }
+        if self.env.is_empty() {
+            state.skip_field("env")?;
+        } else {
+            state.serialize_field("env", &self.env)?;
+        }
+        if self.env_files.is_empty() {
+            state.skip_field("env_files")?;
+        } else {
+            state.serialize_field("env_files", &self.env_files)?;
+        }
+        match &self.shell {
+            Some(_) => state.serialize_field("shell", &self.shell)?,
+            None => state.skip_field("shell")?,
+        }
+        match self.exec {
+            Some(_) => state.serialize_field("exec", &self.exec)?,
+            None => state.skip_field("exec")?,
+        }
         state.serialize_field("projects", &self.projects)?;
+        if self.groups.is_empty() {
+            state.skip_field("groups")?;
+        } else {
+            state.serialize_field("groups", &self.groups)?;
+        }
         state.serialize_field("scripts", &self.scripts)?;
+        if self.profiles.is_empty() {
+            state.skip_field("profiles")?;
+        } else {
+            state.serialize_field("profiles", &self.profiles)?;
+        }
 
         state.end()
     }
@@ -98,10 +326,34 @@ impl Serialize for ProjectConfig {
     where
         S: serde::Serializer,
     {
-        let mut state = serializer.serialize_struct("ProjectConfig", 2)?;
+        let mut state = serializer.serialize_struct("ProjectConfig", 8)?;
 
         state.serialize_field("project_name", &self.project_name)?;
         state.serialize_field("project_description", &self.project_description)?;
+        match &self.path {
+            Some(_) => state.serialize_field("path", &self.path)?,
+            None => state.skip_field("path")?,
+        }
+        if self.enabled {
+            state.skip_field("enabled")?;
+        } else {
+            state.serialize_field("enabled", &self.enabled)?;
+        }
+        if self.tags.is_empty() {
+            state.skip_field("tags")?;
+        } else {
+            state.serialize_field("tags", &self.tags)?;
+        }
+        if self.env.is_empty() {
+            state.skip_field("env")?;
+        } else {
+            state.serialize_field("env", &self.env)?;
+        }
+        if self.env_files.is_empty() {
+            state.skip_field("env_files")?;
+        } else {
+            state.serialize_field("env_files", &self.env_files)?;
+        }
         state.serialize_field("stages", &self.stages)?;
 
         state.end()
@@ -114,12 +366,40 @@ impl Serialize for StageConfig {
     where
         S: serde::Serializer,
     {
-        let mut state = serializer.serialize_struct("StageConfig", 4)?;
+        let mut state = serializer.serialize_struct("StageConfig", 10)?;
 
         state.serialize_field("stage_name", &self.stage_name)?;
+        if self.aliases.is_empty() {
+            state.skip_field("aliases")?;
+        } else {
+            state.serialize_field("aliases", &self.aliases)?;
+        }
         state.serialize_field("stage_description", &self.stage_description)?;
         state.serialize_field("prerequisites", &self.prerequisites)?;
         state.serialize_field("commands", &self.commands)?;
+        match &self.shell {
+            Some(_) => state.serialize_field("shell", &self.shell)?,
+            None => state.skip_field("shell")?,
+        }
+        match self.exec {
+            Some(_) => state.serialize_field("exec", &self.exec)?,
+            None => state.skip_field("exec")?,
+        }
+        if self.continue_on_error {
+            state.serialize_field("continue_on_error", &self.continue_on_error)?;
+        } else {
+            state.skip_field("continue_on_error")?;
+        }
+        if self.env.is_empty() {
+            state.skip_field("env")?;
+        } else {
+            state.serialize_field("env", &self.env)?;
+        }
+        if self.env_files.is_empty() {
+            state.skip_field("env_files")?;
+        } else {
+            state.serialize_field("env_files", &self.env_files)?;
+        }
 
         state.end()
     }
@@ -131,27 +411,145 @@ impl Serialize for ScriptConfig {
     where
         S: serde::Serializer,
     {
-        let mut state = serializer.serialize_struct("ScriptConfig", 2)?;
+        let mut state = serializer.serialize_struct("ScriptConfig", 8)?;
 
         state.serialize_field("script_name", &self.script_name)?;
         state.serialize_field("script_description", &self.script_description)?;
         state.serialize_field("commands", &self.commands)?;
+        match &self.shell {
+            Some(_) => state.serialize_field("shell", &self.shell)?,
+            None => state.skip_field("shell")?,
+        }
+        match self.exec {
+            Some(_) => state.serialize_field("exec", &self.exec)?,
+            None => state.skip_field("exec")?,
+        }
 
+        if self.env.is_empty() {
+            state.skip_field("env")?;
+        } else {
+            state.serialize_field("env", &self.env)?;
+        }
+        if self.env_files.is_empty() {
+            state.skip_field("env_files")?;
+        } else {
+            state.serialize_field("env_files", &self.env_files)?;
+        }
+        if self.params.is_empty() {
+            state.skip_field("params")?;
+        } else {
+            state.serialize_field("params", &self.params)?;
+        }
         state.end()
     }
 }
 
-// Serialize StackConfig to JSON and write it to a file
-pub fn serialize_stack_config_to_file(config: &StackConfig, file_path: &str) -> Result<(), std::io::Error> {
-    let json_str = serde_json::to_string(config)?;
+// Implement Serialize trait for ParamConfig
+impl Serialize for ParamConfig {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        let mut state = serializer.serialize_struct("ParamConfig", 4)?;
 
-    // Write the JSON string to the file
-    std::fs::write(file_path, json_str)?;
+        state.serialize_field("param_name", &self.param_name)?;
+        state.serialize_field("param_description", &self.param_description)?;
+        state.serialize_field("param_type", &self.param_type)?;
+        match &self.default {
+            Some(_) => state.serialize_field("default", &self.default)?,
+            None => state.skip_field("default")?,
+        }
+        state.end()
+    }
+}
 
-    Ok(())
+// Implement Serialize trait for ProfileConfig
+impl Serialize for ProfileConfig {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        let mut state = serializer.serialize_struct("ProfileConfig", 5)?;
+
+        state.serialize_field("profile_name", &self.profile_name)?;
+        state.serialize_field("profile_description", &self.profile_description)?;
+        if self.variables.is_empty() {
+            state.skip_field("variables")?;
+        } else {
+            state.serialize_field("variables", &self.variables)?;
+        }
+        if self.env.is_empty() {
+            state.skip_field("env")?;
+        } else {
+            state.serialize_field("env", &self.env)?;
+        }
+        match &self.projects {
+            Some(_) => state.serialize_field("projects", &self.projects)?,
+            None => state.skip_field("projects")?,
+        }
+        state.end()
+    }
+}
+
+// Implement Serialize trait for CommandConfig
+impl Serialize for CommandConfig {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        match self {
+            CommandConfig::Simple(run) => serializer.serialize_str(run),
+            CommandConfig::Detailed(details) => details.serialize(serializer),
+        }
+    }
 }
 
+// Implement Serialize trait for CommandDetails
+impl Serialize for CommandDetails {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        let mut state = serializer.serialize_struct("CommandDetails", 6)?;
+
+        state.serialize_field("run", &self.run)?;
+        match &self.cwd {
+            Some(_) => state.serialize_field("cwd", &self.cwd)?,
+            None => state.skip_field("cwd")?,
+        }
+        if self.env.is_empty() {
+            state.skip_field("env")?;
+        } else {
+            state.serialize_field("env", &self.env)?;
+        }
+        match &self.timeout {
+            Some(_) => state.serialize_field("timeout", &self.timeout)?,
+            None => state.skip_field("timeout")?,
+        }
+        if self.ignore_errors {
+            state.serialize_field("ignore_errors", &self.ignore_errors)?;
+        } else {
+            state.skip_field("ignore_errors")?;
+        }
+        if self.retries > 0 {
+            state.serialize_field("retries", &self.retries)?;
+        } else {
+            state.skip_field("retries")?;
+        }
 
+        state.end()
+    }
+}
 
+// Serialize StackConfig in the format of the file's extension and write it to the file, keeping the comments of JSON files
+pub fn serialize_stack_config_to_file(config: &StackConfig, file_path: &str) -> Result<(), std::io::Error> {
+    let format = stack_format_util::get_stack_format(file_path);
+    let existing = std::fs::read_to_string(file_path).ok();
+    let content = stack_format_util::serialize_stack_config(config, format, existing.as_deref())
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
 
+    // Write the serialized string to the file
+    std::fs::write(file_path, content)?;
 
+    Ok(())
+}
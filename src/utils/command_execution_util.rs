@@ -0,0 +1,769 @@
+use colored::Colorize;
+use std::collections::{BTreeMap, HashSet};
+use std::io::{BufRead, BufReader, Read};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::PathBuf;
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::models::stack_config_json::{CommandConfig, CommandDetails, StackConfig};
+use crate::utils::{environment_util, generic_utils, interpolation_util, output_util, stack_path_util};
+use crate::utils::stage_graph_util::{self, StageReference};
+
+// How each command string is turned into a process.
+#[derive(Debug, Clone)]
+pub enum ExecutionMode {
+    // Hand the whole command to a shell, e.g. ["sh", "-c"] followed by the command.
+    Shell(Vec<String>),
+    // Tokenize the command with shell-words rules and run the program directly, without a shell.
+    Exec,
+}
+
+impl ExecutionMode {
+    // Shells that read the command with "-c" take the arguments after it as "$1", "$2", ... and "$@". "cmd /C" and
+    // PowerShell's "-Command" have nothing like it, commands there use "${args}".
+    pub fn takes_arguments(&self) -> bool {
+        matches!(self, ExecutionMode::Shell(shell_args) if shell_args.iter().skip(1).any(|arg| arg == "-c"))
+    }
+}
+
+fn get_default_shell() -> &'static str {
+    if cfg!(windows) {
+        "cmd /C"
+    } else {
+        "sh -c"
+    }
+}
+
+// Resolves the execution mode of a stage or script, the innermost "shell" / "exec" setting wins.
+pub fn resolve_execution_mode(
+    shells: &[&Option<String>],
+    execs: &[&Option<bool>],
+) -> Result<ExecutionMode, String> {
+    let exec = execs.iter().rev().find_map(|exec| **exec).unwrap_or(false);
+    if exec {
+        return Ok(ExecutionMode::Exec);
+    }
+
+    let shell = shells
+        .iter()
+        .rev()
+        .find_map(|shell| shell.as_deref())
+        .unwrap_or(get_default_shell());
+    let mut shell_args = match shell_words::split(shell) {
+        Ok(shell_args) => shell_args,
+        Err(e) => return Err(format!("Invalid shell \"{}\": {}", shell, e)),
+    };
+    if shell_args.is_empty() {
+        return Err("Shell is empty".to_string());
+    }
+    // "bash -euo pipefail" still needs the flag that makes the shell read the command from its arguments
+    if !shell_args
+        .iter()
+        .skip(1)
+        .any(|arg| ["-c", "/C", "/c", "-Command", "-command"].contains(&arg.as_str()))
+    {
+        shell_args.push(if shell_args[0].ends_with("cmd") || shell_args[0].ends_with("cmd.exe") {
+            "/C".to_string()
+        } else {
+            "-c".to_string()
+        });
+    }
+    Ok(ExecutionMode::Shell(shell_args))
+}
+
+// Shells that take arguments get the forwarded ones as "sh -c <command> ldot <args...>", "ldot" being "$0"
+fn build_command(command: &str, mode: &ExecutionMode, args: &[String]) -> Result<Command, String> {
+    match mode {
+        ExecutionMode::Shell(shell_args) => {
+            let mut cmd_execution = Command::new(&shell_args[0]);
+            cmd_execution.args(&shell_args[1..]).arg(command);
+            if mode.takes_arguments() {
+                cmd_execution.arg("ldot").args(args);
+            }
+            Ok(cmd_execution)
+        }
+        ExecutionMode::Exec => {
+            let args = match shell_words::split(command) {
+                Ok(args) => args,
+                Err(e) => return Err(format!("Could not tokenize command: {}", e)),
+            };
+            if args.is_empty() {
+                return Err("Command is empty".to_string());
+            }
+            let mut cmd_execution = Command::new(&args[0]);
+            cmd_execution.args(&args[1..]);
+            Ok(cmd_execution)
+        }
+    }
+}
+
+fn get_stage_context(
+    stack_config: &StackConfig,
+    stack_file: &str,
+    reference: &StageReference,
+    args: &[String],
+    keep_going: bool,
+) -> Result<CommandContext, String> {
+    let project = stage_graph_util::find_project(stack_config, &reference.project).unwrap();
+    let project_stage = stage_graph_util::find_stage(stack_config, reference).unwrap();
+    let mode = resolve_execution_mode(
+        &[&stack_config.shell, &project_stage.shell],
+        &[&stack_config.exec, &project_stage.exec],
+    )?;
+    let env = environment_util::get_stage_environment(stack_config, stack_file, project, Some(project_stage))?;
+    let working_directory = stack_path_util::get_default_working_directory(stack_config, stack_file, Some(project));
+    Ok(CommandContext {
+        mode,
+        failure_policy: FailurePolicy::from_flags(keep_going, project_stage.continue_on_error),
+        working_directory: Some(working_directory),
+        env,
+        args: args.to_vec(),
+    })
+}
+
+// Interpolates "${...}" references in a stage's commands, with the arguments forwarded to the stage
+fn get_stage_commands(
+    stack_config: &StackConfig,
+    stack_file: &str,
+    reference: &StageReference,
+    context: &CommandContext,
+) -> Result<Vec<CommandConfig>, String> {
+    let project = stage_graph_util::find_project(stack_config, &reference.project).unwrap();
+    let project_stage = stage_graph_util::find_stage(stack_config, reference).unwrap();
+    let mut scope = interpolation_util::get_stage_scope(stack_config, stack_file, project, &reference.stage);
+    scope.set_arguments(&context.args);
+    interpolation_util::forward_arguments(&project_stage.commands, &context.args, context.mode.takes_arguments())
+        .iter()
+        .map(|command| interpolation_util::interpolate_command(command, &scope))
+        .collect()
+}
+
+// Exit codes used by ldot itself. When a command fails, ldot exits with that command's exit code instead.
+pub const EXIT_FAILURE: i32 = 1; // Invalid arguments, unknown stack, project, stage or script, failed validation
+pub const EXIT_STACK_FILE_ERROR: i32 = 2; // A stack file could not be read or parsed
+pub const EXIT_COMMAND_TIMED_OUT: i32 = 124; // A command ran longer than its timeout
+pub const EXIT_COMMAND_NOT_STARTED: i32 = 127; // A command could not be started, e.g. the program does not exist
+
+// The "Commands Executed" summary of a run, and the exit code ldot should finish with.
+pub struct ExecutionReport {
+    pub commands: Vec<String>,
+    pub exit_code: i32,
+}
+
+impl ExecutionReport {
+    pub fn new() -> ExecutionReport {
+        ExecutionReport {
+            commands: vec![],
+            exit_code: 0,
+        }
+    }
+
+    // A run that could not start at all, e.g. because the stage does not exist
+    pub fn error(message: String, exit_code: i32) -> ExecutionReport {
+        ExecutionReport {
+            commands: vec![message],
+            exit_code,
+        }
+    }
+
+    pub fn succeeded(&self) -> bool {
+        self.exit_code == 0
+    }
+
+    // Only the first failure decides the exit code
+    fn record_failure(&mut self, exit_code: i32) {
+        if self.exit_code == 0 {
+            self.exit_code = exit_code;
+        }
+    }
+}
+
+// What happens to the remaining commands after one fails
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FailurePolicy {
+    // Skip the remaining commands and fail
+    Stop,
+    // Run the remaining commands, but still fail (--keep-going)
+    KeepGoing,
+    // Run the remaining commands and only report the failure (continue_on_error)
+    Continue,
+}
+
+impl FailurePolicy {
+    pub fn from_flags(keep_going: bool, continue_on_error: bool) -> FailurePolicy {
+        if continue_on_error {
+            FailurePolicy::Continue
+        } else if keep_going {
+            FailurePolicy::KeepGoing
+        } else {
+            FailurePolicy::Stop
+        }
+    }
+}
+
+// Everything a list of commands needs to run, besides the commands themselves
+#[derive(Debug, Clone)]
+pub struct CommandContext {
+    pub mode: ExecutionMode,
+    pub failure_policy: FailurePolicy,
+    // Directory commands run in, a relative "cwd" is resolved against it
+    pub working_directory: Option<PathBuf>,
+    // Environment variables set for every command, a command's own "env" takes precedence
+    pub env: BTreeMap<String, String>,
+    // Arguments forwarded from the command line, shells that take arguments get them as "$@"
+    pub args: Vec<String>,
+}
+
+struct StageTask {
+    reference: StageReference,
+    commands: Vec<CommandConfig>,
+    context: CommandContext,
+}
+
+// Runs the stages of an execution order, starting a stage once all of its prerequisites have finished.
+// With more than one job, independent stages run concurrently and their output is prefixed with "project:stage".
+// The run stops after the first failing stage unless keep_going is set, stages whose prerequisites failed are always skipped.
+// Arguments from the command line are forwarded to the targets only, not to their prerequisites.
+pub fn execute_stage_graph(
+    stack_config: &StackConfig,
+    stack_file: &str,
+    execution_order: &[StageReference],
+    targets: &[StageReference],
+    args: &[String],
+    jobs: usize,
+    keep_going: bool,
+) -> ExecutionReport {
+    let mut report = ExecutionReport::new();
+
+    let mut pending: Vec<(StageTask, Vec<StageReference>)> = vec![];
+    for reference in execution_order {
+        let dependencies = match stage_graph_util::get_stage_dependencies(stack_config, reference) {
+            Ok(dependencies) => dependencies,
+            Err(e) => return ExecutionReport::error(e, EXIT_FAILURE),
+        };
+        let stage_args = if targets.contains(reference) { args } else { &[] };
+        let context = match get_stage_context(stack_config, stack_file, reference, stage_args, keep_going) {
+            Ok(context) => context,
+            Err(e) => return ExecutionReport::error(format!("{} in: {}", e, reference), EXIT_FAILURE),
+        };
+        let commands = match get_stage_commands(stack_config, stack_file, reference, &context) {
+            Ok(commands) => commands,
+            Err(e) => return ExecutionReport::error(format!("{} in: {}", e, reference), EXIT_FAILURE),
+        };
+        let task = StageTask {
+            reference: reference.clone(),
+            commands,
+            context,
+        };
+        pending.push((task, dependencies));
+    }
+
+    let (sender, receiver) = mpsc::channel();
+    let mut completed: HashSet<StageReference> = HashSet::new();
+    let mut failed: HashSet<StageReference> = HashSet::new();
+    let mut stopped = false;
+    let mut running: HashSet<StageReference> = HashSet::new();
+    loop {
+        while running.len() < jobs.max(1) && !stopped {
+            while let Some(index) = pending
+                .iter()
+                .position(|(_, dependencies)| dependencies.iter().any(|dependency| failed.contains(dependency)))
+            {
+                let (task, _) = pending.remove(index);
+                report.commands.push(
+                    format!("[{}] skipped, a prerequisite failed", task.reference)
+                        .yellow()
+                        .to_string(),
+                );
+                failed.insert(task.reference);
+            }
+
+            let ready = pending
+                .iter()
+                .position(|(_, dependencies)| dependencies.iter().all(|dependency| completed.contains(dependency)));
+            let (task, _) = match ready {
+                Some(index) => pending.remove(index),
+                None => break,
+            };
+
+            // Aggregate stages have no commands, they are done once their prerequisites are
+            if task.commands.is_empty() {
+                if jobs <= 1 {
+                    output_util::print_info(format!("\n{} {}", "Stage".blue(), task.reference.to_string().blue()));
+                    output_util::print_info("Aggregate stage, its prerequisites have finished");
+                } else {
+                    output_util::print_info(format!("{} {} (aggregate)", "Finished stage".blue(), task.reference.to_string().blue()));
+                }
+                completed.insert(task.reference);
+                continue;
+            }
+
+            if jobs <= 1 {
+                output_util::print_info(format!("\n{} {}", "Stage".blue(), task.reference.to_string().blue()));
+                output_util::print_info(format!("Executing {} commands", task.commands.len()));
+                let stage_report = execute_command_from_list(task.commands, &task.context, None);
+                stopped = record_stage_report(&mut report, &task.reference, stage_report, &mut completed, &mut failed) && !keep_going;
+                continue;
+            }
+
+            let stage_sender = sender.clone();
+            output_util::print_info(format!(
+                "{} {} ({} commands)",
+                "Starting stage".blue(),
+                task.reference.to_string().blue(),
+                task.commands.len()
+            ));
+            running.insert(task.reference.clone());
+            thread::spawn(move || {
+                let prefix = task.reference.to_string();
+                let stage_report =
+                    catch_lost_stage(|| execute_command_from_list(task.commands, &task.context, Some(&prefix)));
+                let _ = stage_sender.send((task.reference, stage_report));
+            });
+        }
+        if running.is_empty() {
+            break;
+        }
+
+        let (reference, stage_report) = match receiver.recv() {
+            Ok(received) => received,
+            // The workers are gone without reporting, so their stages can't have succeeded
+            Err(_) => {
+                for reference in running.drain() {
+                    record_stage_report(&mut report, &reference, lost_stage_report(), &mut completed, &mut failed);
+                }
+                stopped = !keep_going;
+                continue;
+            }
+        };
+        running.remove(&reference);
+        output_util::print_info(format!("{} {}", "Finished stage".blue(), reference.to_string().blue()));
+        if record_stage_report(&mut report, &reference, stage_report, &mut completed, &mut failed) && !keep_going {
+            stopped = true;
+        }
+    }
+
+    if stopped && !pending.is_empty() {
+        println!(
+            "{}",
+            format!("Stopped after the first failure, {} stages were not run. Use --keep-going to run them anyway.", pending.len())
+                .yellow()
+        );
+        for (task, _) in &pending {
+            report
+                .commands
+                .push(format!("[{}] skipped, the run stopped", task.reference).yellow().to_string());
+        }
+    }
+    report
+}
+
+// A stage whose worker panics fails instead of never reporting back, which would leave the run waiting for it
+fn catch_lost_stage<F: FnOnce() -> ExecutionReport>(run: F) -> ExecutionReport {
+    panic::catch_unwind(AssertUnwindSafe(run)).unwrap_or_else(|_| lost_stage_report())
+}
+
+fn lost_stage_report() -> ExecutionReport {
+    ExecutionReport::error("the stage stopped unexpectedly".bright_red().to_string(), EXIT_FAILURE)
+}
+
+// Adds a stage's results to the overall report, returning whether the stage failed
+fn record_stage_report(
+    report: &mut ExecutionReport,
+    reference: &StageReference,
+    stage_report: ExecutionReport,
+    completed: &mut HashSet<StageReference>,
+    failed: &mut HashSet<StageReference>,
+) -> bool {
+    let succeeded = stage_report.succeeded();
+    for result in stage_report.commands {
+        report.commands.push(format!("[{}] {}", reference, result));
+    }
+    if succeeded {
+        completed.insert(reference.clone());
+        false
+    } else {
+        report.record_failure(stage_report.exit_code);
+        failed.insert(reference.clone());
+        true
+    }
+}
+
+// Runs commands one after another, the failure policy decides what happens after a command fails.
+pub fn execute_command_from_list(
+    commands: Vec<CommandConfig>,
+    context: &CommandContext,
+    output_prefix: Option<&str>,
+) -> ExecutionReport {
+    let mut report = ExecutionReport::new();
+
+    for (index, command_config) in commands.iter().enumerate() {
+        let command = command_config.to_details();
+        let happy_status_code: i32 = 0;
+        let timeout = match &command.timeout {
+            Some(timeout) => match generic_utils::parse_duration(timeout) {
+                Ok(timeout) => Some(timeout),
+                Err(e) => {
+                    println!("{}", e.bright_red());
+                    report.commands.push(format!("\"{}\": {}", &command.run, e).bright_red().to_string());
+                    report.record_failure(EXIT_FAILURE);
+                    break;
+                }
+            },
+            None => None,
+        };
+
+        let attempts = command.retries + 1;
+        let mut attempt = 0;
+        let execution_result = loop {
+            attempt += 1;
+            match output_prefix {
+                Some(prefix) => output_util::print_info(format!("{} > {}", format!("[{}]", prefix).cyan(), command.run.purple())),
+                None => output_util::print_info(format!("\n> {}", command.run.purple())),
+            }
+            let execution_result = match build_command(&command.run, &context.mode, &context.args) {
+                Ok(mut cmd_execution) => match get_working_directory(context, &command) {
+                    Ok(working_directory) => {
+                        if let Some(working_directory) = working_directory {
+                            output_util::print_verbose(format!("Working directory: {}", working_directory.display()));
+                            cmd_execution.current_dir(working_directory);
+                        }
+                        cmd_execution.envs(&context.env);
+                        cmd_execution.envs(&command.env);
+                        run_command(&mut cmd_execution, output_prefix, timeout)
+                    }
+                    Err(e) => Err(std::io::Error::new(std::io::ErrorKind::NotFound, e)),
+                },
+                Err(e) => Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, e)),
+            };
+            let succeeded = matches!(&execution_result, Ok(exit_status) if exit_status.code() == Some(happy_status_code));
+            if succeeded || attempt >= attempts {
+                break execution_result;
+            }
+            println!("{}", format!("Retrying ({}/{})", attempt, command.retries).yellow());
+        };
+
+        let details = describe_command_details(&command, attempt);
+        let failure_exit_code = match execution_result {
+            Ok(exit_status) => {
+                if Some(exit_status.code()) == Some(Some(happy_status_code)) {
+                    report.commands.push(
+                        format!(
+                            "\"{}\" exit code: {:?}{}",
+                            &command.run,
+                            exit_status.code().unwrap(),
+                            details
+                        )
+                        .bright_green()
+                        .to_string(),
+                    );
+                    None
+                } else {
+                    let exit_code = get_exit_code(&exit_status);
+                    report.commands.push(
+                        format!("\"{}\" exit code: {}{}", &command.run, exit_code, details)
+                            .yellow()
+                            .to_string(),
+                    );
+                    Some(exit_code)
+                }
+            }
+            Err(err) => {
+                println!("{}", format!("{}", err).bright_red());
+                report.commands.push(
+                    format!("\"{}\": {}{}", &command.run, err, details)
+                        .bright_red()
+                        .to_string(),
+                );
+                match err.kind() {
+                    std::io::ErrorKind::TimedOut => Some(EXIT_COMMAND_TIMED_OUT),
+                    _ => Some(EXIT_COMMAND_NOT_STARTED),
+                }
+            }
+        };
+
+        if let Some(exit_code) = failure_exit_code {
+            if command.ignore_errors {
+                continue;
+            }
+            if context.failure_policy == FailurePolicy::Continue {
+                report.commands.push("continuing, the stage allows errors".yellow().to_string());
+                continue;
+            }
+            report.record_failure(exit_code);
+            if context.failure_policy == FailurePolicy::KeepGoing {
+                continue;
+            }
+            for skipped in &commands[index + 1..] {
+                report.commands.push(format!("\"{}\" skipped", skipped.to_details().run).yellow().to_string());
+            }
+            break;
+        }
+    }
+    report
+}
+
+// A command's "cwd" is resolved against the project directory, when there is one
+fn get_working_directory(context: &CommandContext, command: &CommandDetails) -> Result<Option<PathBuf>, String> {
+    let working_directory = match (&context.working_directory, &command.cwd) {
+        (Some(directory), Some(cwd)) => directory.join(cwd),
+        (Some(directory), None) => directory.to_path_buf(),
+        (None, Some(cwd)) => PathBuf::from(cwd),
+        (None, None) => return Ok(None),
+    };
+    if !working_directory.is_dir() {
+        return Err(format!(
+            "Working directory does not exist: {}",
+            working_directory.display()
+        ));
+    }
+    Ok(Some(working_directory))
+}
+
+fn get_exit_code(exit_status: &ExitStatus) -> i32 {
+    if let Some(code) = exit_status.code() {
+        return code;
+    }
+    // Killed by a signal, report it the way shells do
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::ExitStatusExt;
+        if let Some(signal) = exit_status.signal() {
+            return 128 + signal;
+        }
+    }
+    EXIT_FAILURE
+}
+
+// Lists the settings of a command object for the "Commands Executed" summary, e.g. " (cwd: web, timeout: 90s)"
+fn describe_command_details(command: &CommandDetails, attempts: u32) -> String {
+    let mut details: Vec<String> = vec![];
+    if let Some(cwd) = &command.cwd {
+        details.push(format!("cwd: {}", cwd));
+    }
+    if !command.env.is_empty() {
+        let keys: Vec<&str> = command.env.keys().map(|key| key.as_str()).collect();
+        details.push(format!("env: {}", keys.join(", ")));
+    }
+    if let Some(timeout) = &command.timeout {
+        details.push(format!("timeout: {}", timeout));
+    }
+    if command.retries > 0 {
+        details.push(format!("attempts: {}/{}", attempts, command.retries + 1));
+    }
+    if command.ignore_errors {
+        details.push("errors ignored".to_string());
+    }
+    if details.is_empty() {
+        "".to_string()
+    } else {
+        format!(" ({})", details.join(", "))
+    }
+}
+
+// How long the output of a killed command is still forwarded, processes that left its process group may keep
+// its pipes open
+const FORWARD_GRACE_PERIOD: Duration = Duration::from_millis(500);
+
+// Runs a command to completion, killing it once the timeout elapses.
+// With a prefix, stdout and stderr are captured and every line is re-printed behind it.
+fn run_command(
+    cmd_execution: &mut Command,
+    output_prefix: Option<&str>,
+    timeout: Option<Duration>,
+) -> std::io::Result<ExitStatus> {
+    let prefix = output_prefix.map(|prefix| format!("[{}]", prefix).cyan().to_string());
+    if prefix.is_some() {
+        cmd_execution.stdout(Stdio::piped()).stderr(Stdio::piped());
+    }
+    // Commands with a timeout get their own process group, so everything they start is killed with them. Others
+    // stay in the terminal's process group to keep reading input and receiving Ctrl+C.
+    #[cfg(unix)]
+    if timeout.is_some() {
+        use std::os::unix::process::CommandExt;
+        cmd_execution.process_group(0);
+    }
+
+    let mut child = cmd_execution.spawn()?;
+    let mut forwarders = vec![];
+    if let Some(prefix) = prefix {
+        forwarders.extend(forward_lines(child.stdout.take(), prefix.to_string(), false));
+        forwarders.extend(forward_lines(child.stderr.take(), prefix, true));
+    }
+
+    let exit_status = match timeout {
+        Some(timeout) => wait_with_timeout(&mut child, timeout),
+        None => child.wait(),
+    };
+    let forward_deadline = match &exit_status {
+        Err(e) if e.kind() == std::io::ErrorKind::TimedOut => Some(Instant::now() + FORWARD_GRACE_PERIOD),
+        _ => None,
+    };
+    for forwarder in forwarders {
+        // Forwarders still running after the deadline are left behind, they end once the pipe closes
+        while forward_deadline.is_some_and(|deadline| Instant::now() < deadline) && !forwarder.is_finished() {
+            thread::sleep(Duration::from_millis(10));
+        }
+        if forward_deadline.is_none() || forwarder.is_finished() {
+            let _ = forwarder.join();
+        }
+    }
+    exit_status
+}
+
+fn wait_with_timeout(child: &mut Child, timeout: Duration) -> std::io::Result<ExitStatus> {
+    let started = Instant::now();
+    loop {
+        if let Some(exit_status) = child.try_wait()? {
+            return Ok(exit_status);
+        }
+        if started.elapsed() >= timeout {
+            kill_process_group(child);
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::TimedOut,
+                format!("timed out after {:?}", timeout),
+            ));
+        }
+        thread::sleep(Duration::from_millis(50));
+    }
+}
+
+// Kills the process group run_command started the command in, with the processes the command started
+#[cfg(unix)]
+fn kill_process_group(child: &Child) {
+    if let Ok(process_group) = libc::pid_t::try_from(child.id()) {
+        // SAFETY: kill only sends a signal, a negative pid addresses the group led by the child
+        unsafe {
+            libc::kill(-process_group, libc::SIGKILL);
+        }
+    }
+}
+
+#[cfg(not(unix))]
+fn kill_process_group(_child: &Child) {}
+
+fn forward_lines<R: Read + Send + 'static>(
+    stream: Option<R>,
+    prefix: String,
+    to_stderr: bool,
+) -> Option<thread::JoinHandle<()>> {
+    let stream = stream?;
+    Some(thread::spawn(move || {
+        for line in BufReader::new(stream).lines().map_while(Result::ok) {
+            if to_stderr {
+                eprintln!("{} {}", prefix, line);
+            } else {
+                println!("{} {}", prefix, line);
+            }
+        }
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn commands_run_in_the_stack_directory() {
+        let directory = std::env::temp_dir().join(format!("ldot_test_working_directory_{}", std::process::id()));
+        std::fs::create_dir_all(directory.join("web")).unwrap();
+        std::fs::write(directory.join("web").join("marker"), "").unwrap();
+        let stack_file = directory.join("ldot_stack.json");
+        let stack = r#"{
+    "stack_name": "working_directory",
+    "projects": [
+        { "project_name": "app", "stages": [
+            { "stage_name": "check", "commands": [
+                "test -f ldot_stack.json && test \"${project.path}\" = \"$(pwd)\"",
+                { "run": "test -f marker", "cwd": "web" }
+            ] }
+        ] }
+    ],
+    "scripts": []
+}"#;
+        std::fs::write(&stack_file, stack).unwrap();
+        let stack_file = stack_file.display().to_string();
+        // The tests run in the crate directory, not in the stack's
+        assert_ne!(std::env::current_dir().unwrap(), directory);
+        let stack_config = crate::utils::stack_composition_util::read_stack_config(&stack_file).unwrap();
+        let targets = [stage_graph_util::parse_stage_reference("app:check", "").unwrap()];
+        let report = execute_stage_graph(&stack_config, &stack_file, &targets, &targets, &[], 1, false);
+        std::fs::remove_dir_all(&directory).unwrap();
+        assert!(report.succeeded(), "{:?}", report.commands);
+    }
+
+    #[test]
+    fn shells_with_dash_c_take_arguments() {
+        assert!(resolve_execution_mode(&[&Some("bash -eu".to_string())], &[]).unwrap().takes_arguments());
+        assert!(!resolve_execution_mode(&[&Some("cmd".to_string())], &[]).unwrap().takes_arguments());
+        assert!(!resolve_execution_mode(&[&Some("pwsh -Command".to_string())], &[]).unwrap().takes_arguments());
+        assert!(!resolve_execution_mode(&[], &[&Some(true)]).unwrap().takes_arguments());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn arguments_are_positionals_of_the_shell() {
+        let mode = resolve_execution_mode(&[&Some("sh -c".to_string())], &[]).unwrap();
+        let output = build_command("printf '[%s]' \"$@\"", &mode, &args(&["a b", "c"]))
+            .unwrap()
+            .output()
+            .unwrap();
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "[a b][c]");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn args_variable_is_quoted_for_the_shell() {
+        let mode = resolve_execution_mode(&[&Some("sh -c".to_string())], &[]).unwrap();
+        let forwarded = args(&["a b", "$(echo injected)"]);
+        let mut scope = interpolation_util::VariableScope::new();
+        scope.set_arguments(&forwarded);
+        let command = CommandConfig::Simple("printf '[%s]' ${args}".to_string());
+        let command = interpolation_util::interpolate_command(&command, &scope).unwrap();
+        let output = build_command(&command.to_details().run, &mode, &forwarded)
+            .unwrap()
+            .output()
+            .unwrap();
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "[a b][$(echo injected)]");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn commands_that_time_out_exit_with_124() {
+        let context = CommandContext {
+            mode: resolve_execution_mode(&[&Some("sh -c".to_string())], &[]).unwrap(),
+            failure_policy: FailurePolicy::Stop,
+            working_directory: None,
+            env: BTreeMap::new(),
+            args: vec![],
+        };
+        let command = CommandConfig::Detailed(CommandDetails {
+            run: "sleep 5".to_string(),
+            timeout: Some("100ms".to_string()),
+            ..Default::default()
+        });
+        let started = Instant::now();
+        let report = execute_command_from_list(vec![command, CommandConfig::Simple("true".to_string())], &context, None);
+        assert!(started.elapsed() < Duration::from_secs(4));
+        assert_eq!(report.exit_code, EXIT_COMMAND_TIMED_OUT);
+        assert!(report.commands.iter().any(|result| result.contains("skipped")), "{:?}", report.commands);
+    }
+
+    #[test]
+    fn a_panicking_stage_fails() {
+        let report = catch_lost_stage(|| panic!("worker lost"));
+        assert_eq!(report.exit_code, EXIT_FAILURE);
+        assert!(report.commands[0].contains("the stage stopped unexpectedly"));
+        assert!(catch_lost_stage(ExecutionReport::new).succeeded());
+    }
+}
@@ -0,0 +1,326 @@
+use std::collections::BTreeMap;
+
+use clap::{Command, ValueEnum};
+use clap_complete::Shell;
+
+use crate::models::ldot_config_json::Configuration;
+use crate::models::stack_config_json::StackConfig;
+use crate::utils::stage_graph_util::ProjectSelector;
+use crate::utils::{configuration_util, ldot_stack_util, stack_composition_util};
+
+// Shells "ldot completions" writes a script for
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum CompletionShell {
+    Bash,
+    Zsh,
+    Fish,
+    Elvish,
+}
+
+impl CompletionShell {
+    pub fn to_clap_shell(self) -> Shell {
+        match self {
+            CompletionShell::Bash => Shell::Bash,
+            CompletionShell::Zsh => Shell::Zsh,
+            CompletionShell::Fish => Shell::Fish,
+            CompletionShell::Elvish => Shell::Elvish,
+        }
+    }
+}
+
+// Hidden command the completion scripts call, "ldot __complete -- <words>"
+pub const COMPLETE_COMMAND: &str = "__complete";
+
+// Added after the script clap generates. It asks "ldot __complete" for the names of stacks, projects, stages and
+// scripts, and falls back to the generated completion of subcommands and flags when there are none.
+pub fn get_dynamic_completion_script(shell: CompletionShell) -> &'static str {
+    match shell {
+        CompletionShell::Bash => {
+            r#"
+_ldot_dynamic() {
+    local candidates
+    candidates=$(ldot __complete -- "${COMP_WORDS[@]:1:COMP_CWORD}" 2>/dev/null)
+    if [ -n "$candidates" ]; then
+        local IFS=$'\n'
+        COMPREPLY=($(compgen -W "$candidates" -- "${COMP_WORDS[COMP_CWORD]}"))
+        return 0
+    fi
+    _ldot "$@"
+}
+if [[ "${BASH_VERSINFO[0]}" -eq 4 && "${BASH_VERSINFO[1]}" -ge 4 || "${BASH_VERSINFO[0]}" -gt 4 ]]; then
+    complete -F _ldot_dynamic -o nosort -o bashdefault -o default ldot
+else
+    complete -F _ldot_dynamic -o bashdefault -o default ldot
+fi
+"#
+        }
+        CompletionShell::Zsh => {
+            r#"
+_ldot_dynamic() {
+    local -a candidates
+    candidates=(${(f)"$(ldot __complete -- "${(@)words[2,CURRENT]}" 2>/dev/null)"})
+    if (( ${#candidates} )); then
+        compadd -a candidates
+    else
+        _ldot "$@"
+    fi
+}
+compdef _ldot_dynamic ldot
+"#
+        }
+        CompletionShell::Fish => {
+            r#"
+complete -c ldot -f -a '(ldot __complete -- (commandline -opc)[2..] (commandline -ct) 2>/dev/null)'
+"#
+        }
+        CompletionShell::Elvish => {
+            r#"
+var ldot-generated-completer = $edit:completion:arg-completer[ldot]
+set edit:completion:arg-completer[ldot] = {|@words|
+    var candidates = [(ldot __complete -- (all $words[1..]) 2>/dev/null)]
+    if (> (count $candidates) 0) {
+        all $candidates
+    } else {
+        $ldot-generated-completer $@words
+    }
+}
+"#
+        }
+    }
+}
+
+// The words of a command line, read the way clap reads them: the subcommands, the positional arguments and the
+// values of options by argument id
+struct CommandLine {
+    subcommands: Vec<String>,
+    positionals: Vec<String>,
+    options: BTreeMap<String, Vec<String>>,
+    // The option the word being completed is the value of
+    pending_option: Option<String>,
+    after_separator: bool,
+}
+
+fn parse_command_line(command: &Command, words: &[String]) -> CommandLine {
+    let mut command_line = CommandLine {
+        subcommands: vec![],
+        positionals: vec![],
+        options: BTreeMap::new(),
+        pending_option: None,
+        after_separator: false,
+    };
+    let mut subcommand = command;
+    for word in words {
+        if let Some(option) = command_line.pending_option.take() {
+            command_line.options.entry(option).or_default().push(word.to_string());
+            continue;
+        }
+        if command_line.after_separator {
+            continue;
+        }
+        if word == "--" {
+            command_line.after_separator = true;
+            continue;
+        }
+        let option = if let Some(long) = word.strip_prefix("--") {
+            let (name, value) = match long.split_once('=') {
+                Some((name, value)) => (name, Some(value)),
+                None => (long, None),
+            };
+            subcommand
+                .get_arguments()
+                .find(|arg| arg.get_long() == Some(name))
+                .map(|arg| (arg, value))
+        } else if word.len() > 1 && word.starts_with('-') {
+            // Only the last flag of a group such as "-kj" can take a value
+            let short = word.chars().last().unwrap_or_default();
+            subcommand
+                .get_arguments()
+                .find(|arg| arg.get_short() == Some(short))
+                .map(|arg| (arg, None))
+        } else {
+            None
+        };
+        if word.starts_with('-') && word.len() > 1 {
+            if let Some((arg, value)) = option {
+                let id = arg.get_id().to_string();
+                let values = command_line.options.entry(id.to_string()).or_default();
+                match value {
+                    Some(value) => values.push(value.to_string()),
+                    None if arg.get_action().takes_values() => command_line.pending_option = Some(id),
+                    None => {}
+                }
+            }
+            continue;
+        }
+        if command_line.positionals.is_empty() {
+            if let Some(found) = subcommand.find_subcommand(word) {
+                subcommand = found;
+                command_line.subcommands.push(found.get_name().to_string());
+                continue;
+            }
+        }
+        command_line.positionals.push(word.to_string());
+    }
+    command_line
+}
+
+// Names that can follow a partial command line, printed by "ldot __complete" one per line. The last
+// word is the one being completed, it may be empty. Positions without names of the stack return nothing, and so
+// does a configuration that can't be read. Completion never prints anything else or writes the configuration.
+pub fn complete(mut command: Command, words: &[String]) -> Vec<String> {
+    let config = match configuration_util::read_configuration() {
+        Ok(config) => config,
+        Err(_) => return vec![],
+    };
+    command.build();
+    let (current, words) = match words.split_last() {
+        Some((current, words)) => (current, words),
+        None => return vec![],
+    };
+    let command_line = parse_command_line(&command, words);
+    if command_line.after_separator {
+        return vec![];
+    }
+    let stack_name = command_line
+        .options
+        .get("stack")
+        .and_then(|values| values.last().cloned())
+        .or_else(|| Some(config.default_stack.to_string()))
+        .filter(|stack_name| !stack_name.is_empty());
+    let profile = command_line
+        .options
+        .get("profile")
+        .and_then(|values| values.last().cloned());
+    let read_stack = || read_stack(&config, stack_name.as_deref()?, profile.clone());
+
+    let subcommands: Vec<&str> = command_line.subcommands.iter().map(String::as_str).collect();
+    let positional_count = command_line.positionals.len();
+    let candidates = match (command_line.pending_option.as_deref(), subcommands.as_slice()) {
+        (Some("stack"), _) => get_stack_names(&config),
+        (Some("profile"), _) => read_stack().map(|stack| get_profile_names(&stack)).unwrap_or_default(),
+        (Some("tag"), _) => read_stack().map(|stack| get_tags(&stack)).unwrap_or_default(),
+        (Some("stage"), ["env"]) => match (read_stack(), command_line.positionals.first()) {
+            (Some(stack), Some(project)) => get_stage_names(&stack, project),
+            _ => vec![],
+        },
+        (Some(_), _) => vec![],
+        (None, _) if current.starts_with('-') => vec![],
+        (None, ["execute"]) => {
+            let selects_projects = command_line.options.contains_key("all") || command_line.options.contains_key("tag");
+            match (read_stack(), command_line.positionals.first()) {
+                (Some(stack), _) if selects_projects => get_stage_names(&stack, "*"),
+                (Some(stack), None) => get_project_names(&stack),
+                (Some(stack), Some(project)) => get_stage_names(&stack, project),
+                (None, _) => vec![],
+            }
+        }
+        (None, ["script"]) if positional_count == 0 => read_stack()
+            .map(|stack| {
+                stack
+                    .scripts
+                    .iter()
+                    .map(|script| script.script_name.to_string())
+                    .collect()
+            })
+            .unwrap_or_default(),
+        (None, ["env"]) if positional_count == 0 => {
+            read_stack().map(|stack| get_project_names(&stack)).unwrap_or_default()
+        }
+        (None, ["show"]) if positional_count == 0 => get_show_targets(&config, current),
+        (None, ["config", "default"]) if positional_count == 0 => get_stack_names(&config),
+        (None, ["config", "profile"]) if positional_count == 0 => {
+            read_stack().map(|stack| get_profile_names(&stack)).unwrap_or_default()
+        }
+        _ => vec![],
+    };
+    candidates
+        .into_iter()
+        .filter(|candidate| candidate.starts_with(current.as_str()))
+        .collect()
+}
+
+// A registered stack as it is executed. The stored profile is read from the configuration already loaded.
+fn read_stack(config: &Configuration, stack_name: &str, profile: Option<String>) -> Option<StackConfig> {
+    let stack_file = configuration_util::find_stack_file(config, stack_name)?;
+    let profile = profile.or_else(|| config.active_profiles.get(stack_name).cloned());
+    ldot_stack_util::read_ldot_stack_config(&stack_file, profile).ok()
+}
+
+fn get_stack_names(config: &Configuration) -> Vec<String> {
+    config
+        .registered_stack_files
+        .iter()
+        .filter_map(|stack_file| stack_composition_util::read_stack_name(stack_file).ok())
+        .collect()
+}
+
+fn get_project_names(stack_config: &StackConfig) -> Vec<String> {
+    stack_config
+        .projects
+        .iter()
+        .filter(|project| !project.group)
+        .map(|project| project.project_name.to_string())
+        .collect()
+}
+
+// The stages and aliases of the projects a name or pattern selects, each name once
+fn get_stage_names(stack_config: &StackConfig, project_pattern: &str) -> Vec<String> {
+    let selector = ProjectSelector::Name(project_pattern.to_string());
+    let mut names: Vec<String> = vec![];
+    for project in stack_config.projects.iter().filter(|project| selector.matches(project)) {
+        for stage in &project.stages {
+            for name in std::iter::once(&stage.stage_name).chain(&stage.aliases) {
+                if !names.contains(name) {
+                    names.push(name.to_string());
+                }
+            }
+        }
+    }
+    names
+}
+
+fn get_profile_names(stack_config: &StackConfig) -> Vec<String> {
+    stack_config
+        .profiles
+        .iter()
+        .map(|profile| profile.profile_name.to_string())
+        .collect()
+}
+
+fn get_tags(stack_config: &StackConfig) -> Vec<String> {
+    let mut tags: Vec<String> = stack_config
+        .projects
+        .iter()
+        .flat_map(|project| project.tags.clone())
+        .collect();
+    tags.sort();
+    tags.dedup();
+    tags
+}
+
+// "ldot show" takes "stack", "stack/project" or "stack/project/stage", completed one part at a time
+fn get_show_targets(config: &Configuration, current: &str) -> Vec<String> {
+    let separators = current.matches('/').count();
+    if separators == 0 {
+        return get_stack_names(config);
+    }
+    let stack_name = current.split('/').next().unwrap_or_default();
+    let stack_config = match read_stack(config, stack_name, None) {
+        Some(stack_config) => stack_config,
+        None => return vec![],
+    };
+    let mut targets: Vec<String> = vec![];
+    for project in stack_config.projects.iter().filter(|project| !project.group) {
+        let project_target = format!("{}/{}", stack_name, project.project_name);
+        match separators {
+            1 => targets.push(project_target),
+            _ => targets.extend(
+                project
+                    .stages
+                    .iter()
+                    .map(|stage| format!("{}/{}", project_target, stage.stage_name)),
+            ),
+        }
+    }
+    targets
+}
@@ -1,11 +1,11 @@
 use directories::ProjectDirs;
 use serde_json;
+use std::collections::BTreeMap;
 use std::fs;
 use std::path::Path;
 
 use crate::models::ldot_config_json::Configuration;
-use crate::models::stack_config_json::StackConfig;
-use crate::utils::ldot_stack_util;
+use crate::utils::{migration_util, stack_composition_util};
 
 pub fn get_configuration_directory() -> String {
     if let Some(proj_dirs) = ProjectDirs::from("com", "UndyingSoul",  "Ldot") {
@@ -14,7 +14,7 @@ pub fn get_configuration_directory() -> String {
         // Win: C:\Users\Alice\AppData\Roaming\Foo Corp\Bar App\config
         // Mac: /Users/Alice/Library/Application Support/com.Foo-Corp.Bar-App
     }
-    return "".to_string()
+    "".to_string()
 }
 // Function to read the configuration from the JSON file.
 pub fn get_configuration() -> Result<Configuration, Box<dyn std::error::Error>> {
@@ -35,6 +35,7 @@ pub fn get_configuration() -> Result<Configuration, Box<dyn std::error::Error>>
         let default_configuration: Configuration = Configuration {
             default_stack: String::from(""),
             registered_stack_files: vec![],
+            active_profiles: BTreeMap::new(),
         };
 
         println!(
@@ -47,6 +48,13 @@ pub fn get_configuration() -> Result<Configuration, Box<dyn std::error::Error>>
     }
 }
 
+// Reads the configuration without printing anything or writing a missing or broken one, for completion
+pub fn read_configuration() -> Result<Configuration, String> {
+    let config_file = get_configuration_directory();
+    let config_str = fs::read_to_string(&config_file).map_err(|e| e.to_string())?;
+    serde_json::from_str(&config_str).map_err(|e| e.to_string())
+}
+
 // Function to write the configuration to the JSON file.
 pub fn write_configuration(config: &Configuration) -> Result<(), Box<dyn std::error::Error>> {
     let config_directory = get_configuration_directory();
@@ -64,43 +72,21 @@ pub fn validate_stack_names(stack_name: &str) -> Result<&str, String> {
 
     let mut valid_stack_names: Vec<String> = vec!["".to_string()];
     for stack_file in &config.registered_stack_files {
-        let json_str: String;
-        match std::fs::read_to_string(stack_file) {
-            Ok(stack_file_json_string) => {
-                json_str = stack_file_json_string;
-            }
-            Err(err) => {
-                eprintln!("Error opening stack file: {} {}", stack_file, err);
-                continue;
-            }
+        match stack_composition_util::read_stack_name(stack_file) {
+            Ok(name) => valid_stack_names.push(name),
+            Err(err) => eprintln!("{}", err),
         }
-
-        let stack_config: StackConfig;
-        match serde_json::from_str(&json_str) {
-            Ok(stack_object) => {
-                stack_config = stack_object;
-            }
-            Err(err) => {
-                eprintln!(
-                    "Could not convert stack file to valid configuration: {} {}",
-                    stack_file, err
-                );
-                continue;
-            }
-        }
-
-        valid_stack_names.push(stack_config.stack_name);
     }
 
     if valid_stack_names
         .iter()
-        .any(|valid_stack_name| stack_name.to_string() == valid_stack_name.to_string())
+        .any(|valid_stack_name| stack_name == *valid_stack_name)
     {
         Ok(stack_name)
     } else {
         Err(format!(
             "Could not find stack name in registered stack files: {}",
-            stack_name.to_string()
+            stack_name
         ))
     }
 }
@@ -121,6 +107,31 @@ pub fn set_default_stack(desired_stack: &str) -> Result<(), Box<dyn std::error::
     Ok(())
 }
 
+// The profile stored for a stack with "ldot config profile", if any
+pub fn get_active_profile(stack_name: &str) -> Option<String> {
+    let config = get_configuration().ok()?;
+    config.active_profiles.get(stack_name).cloned()
+}
+
+// Stores the profile of a registered stack, or removes it without a profile name
+pub fn set_active_profile(stack_name: &str, profile_name: Option<&str>) -> Result<(), String> {
+    let mut config = get_configuration().map_err(|e| e.to_string())?;
+    match profile_name {
+        Some(profile_name) => {
+            let stack_file = get_ldot_stack_from_stack_name(stack_name)?;
+            let stack_config = stack_composition_util::read_stack_config(&stack_file)?;
+            if !stack_config.profiles.iter().any(|profile| profile.profile_name == profile_name) {
+                return Err(format!("Profile not found in stack {}: {}", stack_name, profile_name));
+            }
+            config.active_profiles.insert(stack_name.to_string(), profile_name.to_string());
+        }
+        None => {
+            config.active_profiles.remove(stack_name);
+        }
+    }
+    write_configuration(&config).map_err(|e| e.to_string())
+}
+
 // Function to list the registered configurations.
 pub fn list_configurations() -> Result<(), Box<dyn std::error::Error>> {
     let config = get_configuration()?;
@@ -130,48 +141,93 @@ pub fn list_configurations() -> Result<(), Box<dyn std::error::Error>> {
     for file in &config.registered_stack_files {
         println!("    {}", file);
     }
+    if !config.active_profiles.is_empty() {
+        println!("Active Profiles:");
+        for (stack_name, profile_name) in &config.active_profiles {
+            println!("    {}: {}", stack_name, profile_name);
+        }
+    }
     Ok(())
 }
 
+// Finds a registered stack file by its stack_name. The files are not validated here, commands that run a stack
+// check it with ldot_stack_util::check_registered_stack so its errors are not reported as an unknown stack.
 pub fn get_ldot_stack_from_stack_name(stack_name: &str) -> Result<String, String> {
     let mut ldot_stack_file = "".to_string();
 
-    for stack_file in get_configuration().unwrap().registered_stack_files {
-        let latest_stack_file = stack_file;
-        match ldot_stack_util::validate_ldot_stack(latest_stack_file.to_string()) {
-            Ok(stack_config) => {
-                if stack_config.stack_name == stack_name {
-                    ldot_stack_file = latest_stack_file.to_string();
+    let config = get_configuration().map_err(|e| format!("Could not fetch LDOT configuration: {}", e))?;
+    for stack_file in config.registered_stack_files {
+        match stack_composition_util::read_stack_name(&stack_file) {
+            Ok(name) => {
+                if name == stack_name {
+                    ldot_stack_file = stack_file;
                     break;
                 }
             }
             Err(err) => {
-                eprintln!("{} ", err);
+                eprintln!("{}", err);
                 continue;
             }
         }
     }
-    if ldot_stack_file != "" {
+    if !ldot_stack_file.is_empty() {
         Ok(ldot_stack_file.to_string())
     } else {
         Err(format!(
             "Could not find any stack files that have a stack name of: {}",
-            stack_name.to_string()
+            stack_name
         ))
     }
 }
 
+// Finds a registered stack file by its stack_name like get_ldot_stack_from_stack_name, without printing the
+// errors of files that can't be read
+pub fn find_stack_file(config: &Configuration, stack_name: &str) -> Option<String> {
+    config
+        .registered_stack_files
+        .iter()
+        .find(|stack_file| stack_composition_util::read_stack_name(stack_file).is_ok_and(|name| name == stack_name))
+        .cloned()
+}
+
 // // Function to edit the configurations (you can implement this based on your requirements).
 // pub fn edit_configurations() -> Result<(), Box<dyn std::error::Error>> {
 //     // Implement your logic for editing configurations.
 //     Ok(())
 // }
 
+// Replaces a configuration that can't be read. A legacy camelCase configuration is migrated, anything else is
+// regenerated empty. The old file is kept as "<file>.bak" first so nothing is lost. Returns what was done.
+pub fn recover_configuration() -> Result<(Configuration, Vec<String>), Box<dyn std::error::Error>> {
+    let config_file = get_configuration_directory();
+    let source = fs::read_to_string(&config_file)?;
+    let backup_file = format!("{}.bak", config_file);
+    fs::write(&backup_file, &source)?;
+    let mut messages = vec![format!("Kept the previous configuration at: {}", backup_file)];
+
+    let legacy_config = serde_json::from_str::<serde_json::Value>(&source)
+        .ok()
+        .filter(migration_util::is_legacy_config);
+    if let Some(legacy_config) = legacy_config {
+        let migration = migration_util::migrate_config(&legacy_config)?;
+        let config: Configuration = serde_json::from_value(migration.value)?;
+        write_configuration(&config)?;
+        messages.extend(migration.notes.iter().map(|note| format!("Note: {}", note)));
+        messages.push("Migrated legacy configuration".to_string());
+        return Ok((config, messages));
+    }
+
+    let config = regenerate_configuration()?;
+    messages.push("Regenerated configuration".to_string());
+    Ok((config, messages))
+}
+
 // Function to regenerate the configuration file.
 pub fn regenerate_configuration() -> Result<Configuration, Box<dyn std::error::Error>> {
     let default_config = Configuration {
         default_stack: String::from(""),
         registered_stack_files: vec![],
+        active_profiles: BTreeMap::new(),
     };
     write_configuration(&default_config)?;
     Ok(default_config)
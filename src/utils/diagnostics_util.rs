@@ -0,0 +1,246 @@
+use std::collections::HashMap;
+
+use colored::Colorize;
+use serde::Serialize;
+
+use crate::utils::json_source_util::{self, SourceLocation};
+use crate::utils::jsonc_util;
+use crate::utils::stack_format_util::{self, StackFormat};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub message: String,
+    pub file: String,
+    pub path: String,
+    pub line: Option<usize>,
+    pub column: Option<usize>,
+}
+
+struct SourceFile {
+    file: String,
+    source: String,
+    locations: HashMap<String, SourceLocation>,
+}
+
+// Part of the stack that was read from another file, e.g. "projects[2]" from "stages" ... of "api/ldot_project.json"
+struct Origin {
+    path: String,
+    source: usize,
+    source_path: String,
+}
+
+// Collects every problem found in a stack file instead of stopping at the first one.
+// Paths such as "projects[0].stages[1]" are located in the source to report a line and column.
+// Stacks composed of several files report each path in the file it was read from.
+pub struct Diagnostics {
+    file: String,
+    sources: Vec<SourceFile>,
+    origins: Vec<Origin>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl Diagnostics {
+    pub fn new(file: &str, source: &str) -> Diagnostics {
+        let mut diagnostics = Diagnostics {
+            file: file.to_string(),
+            sources: vec![],
+            origins: vec![],
+            diagnostics: vec![],
+        };
+        diagnostics.add_source(file, source);
+        diagnostics
+    }
+
+    pub fn add_source(&mut self, file: &str, source: &str) -> usize {
+        if let Some(index) = self.sources.iter().position(|source_file| source_file.file == file) {
+            return index;
+        }
+        let locations = match stack_format_util::get_stack_format(file) {
+            StackFormat::Json | StackFormat::Json5 => jsonc_util::get_value_locations(source),
+            StackFormat::Yaml => json_source_util::get_yaml_value_locations(source),
+            StackFormat::Toml => json_source_util::get_toml_value_locations(source),
+        };
+        self.sources.push(SourceFile {
+            file: file.to_string(),
+            source: source.to_string(),
+            locations,
+        });
+        self.sources.len() - 1
+    }
+
+    // Reports paths below "path" at "source_path" of a file added with add_source
+    pub fn add_origin(&mut self, path: &str, file: &str, source_path: &str) {
+        if let Some(source) = self.sources.iter().position(|source_file| source_file.file == file) {
+            self.origins.push(Origin {
+                path: path.to_string(),
+                source,
+                source_path: source_path.to_string(),
+            });
+        }
+    }
+
+    // Paths of an upgraded stack don't match its source, later diagnostics are reported without a position
+    pub fn forget_locations(&mut self) {
+        for source_file in &mut self.sources {
+            source_file.locations.clear();
+        }
+    }
+
+    pub fn error(&mut self, path: &str, message: impl Into<String>) {
+        self.add(Severity::Error, path, message.into());
+    }
+
+    pub fn warning(&mut self, path: &str, message: impl Into<String>) {
+        self.add(Severity::Warning, path, message.into());
+    }
+
+    // For errors that already know their file and position, such as syntax errors. serde_json reports column 0 for
+    // errors at the start of a line, e.g. a raw newline inside a string, which is kept as a line without a column.
+    pub fn error_at(&mut self, file: &str, line: usize, column: usize, message: impl Into<String>) {
+        self.diagnostics.push(Diagnostic {
+            severity: Severity::Error,
+            message: message.into(),
+            file: file.to_string(),
+            path: String::new(),
+            line: Some(line).filter(|line| *line > 0),
+            column: Some(column).filter(|column| line > 0 && *column > 0),
+        });
+    }
+
+    fn add(&mut self, severity: Severity, path: &str, message: String) {
+        let (source_file, source_path) = self.find_origin(path);
+        let location = json_source_util::find_location(&source_file.locations, &source_path);
+        self.diagnostics.push(Diagnostic {
+            severity,
+            message,
+            file: source_file.file.to_string(),
+            path: path.to_string(),
+            line: location.map(|location| location.line),
+            column: location.map(|location| location.column),
+        });
+    }
+
+    // The file of the closest origin of a path and where the path is in that file
+    fn find_origin(&self, path: &str) -> (&SourceFile, String) {
+        let origin = self
+            .origins
+            .iter()
+            .filter(|origin| {
+                origin.path.is_empty()
+                    || path == origin.path
+                    || (path.starts_with(&origin.path) && path[origin.path.len()..].starts_with(['.', '[']))
+            })
+            .max_by_key(|origin| origin.path.len());
+        match origin {
+            Some(origin) => {
+                let source_path = match path[origin.path.len()..].trim_start_matches('.') {
+                    "" => origin.source_path.to_string(),
+                    rest => json_source_util::join_path(&origin.source_path, rest),
+                };
+                (&self.sources[origin.source], source_path)
+            }
+            None => (&self.sources[0], path.to_string()),
+        }
+    }
+
+    pub fn error_count(&self) -> usize {
+        self.count(Severity::Error)
+    }
+
+    pub fn warning_count(&self) -> usize {
+        self.count(Severity::Warning)
+    }
+
+    fn count(&self, severity: Severity) -> usize {
+        self.diagnostics
+            .iter()
+            .filter(|diagnostic| diagnostic.severity == severity)
+            .count()
+    }
+
+    pub fn has_errors(&self) -> bool {
+        self.error_count() > 0
+    }
+
+    // A one line description of the errors, for commands that only need to know whether a stack is usable
+    pub fn get_error_summary(&self) -> String {
+        let errors: Vec<&Diagnostic> = self
+            .diagnostics
+            .iter()
+            .filter(|diagnostic| diagnostic.severity == Severity::Error)
+            .collect();
+        let first = match errors.first() {
+            Some(first) => first,
+            None => return String::new(),
+        };
+        let mut summary = format!("{}: {}", self.get_position(first), first.message);
+        if errors.len() > 1 {
+            summary += &format!(" (and {} more errors)", errors.len() - 1);
+        }
+        summary
+    }
+
+    fn get_position(&self, diagnostic: &Diagnostic) -> String {
+        match (diagnostic.line, diagnostic.column) {
+            (Some(line), Some(column)) => format!("{}:{}:{}", diagnostic.file, line, column),
+            (Some(line), None) => format!("{}:{}", diagnostic.file, line),
+            _ => diagnostic.file.to_string(),
+        }
+    }
+
+    // Renders every diagnostic with the offending line of the file, e.g.
+    // error: Project name not unique: api
+    //   --> ldot_stack.json:14:29 (projects[1].project_name)
+    pub fn render(&self) -> String {
+        let mut output = String::new();
+        for diagnostic in &self.diagnostics {
+            let severity = match diagnostic.severity {
+                Severity::Error => "error".red().bold(),
+                Severity::Warning => "warning".yellow().bold(),
+            };
+            output += &format!("{}: {}\n", severity, diagnostic.message);
+            if diagnostic.path.is_empty() {
+                output += &format!("  --> {}\n", self.get_position(diagnostic));
+            } else {
+                output += &format!("  --> {} ({})\n", self.get_position(diagnostic), diagnostic.path);
+            }
+            let source = self
+                .sources
+                .iter()
+                .find(|source_file| source_file.file == diagnostic.file)
+                .map(|source_file| source_file.source.as_str())
+                .unwrap_or_default();
+            if let Some(line) = diagnostic.line {
+                if let Some(source_line) = source.lines().nth(line.saturating_sub(1)) {
+                    let gutter = " ".repeat(line.to_string().len());
+                    output += &format!("{} |\n", gutter);
+                    output += &format!("{} | {}\n", line, source_line);
+                    if let Some(column) = diagnostic.column {
+                        let indent = " ".repeat(column.saturating_sub(1));
+                        output += &format!("{} | {}{}\n", gutter, indent, "^".blue().bold());
+                    }
+                }
+            }
+            output += "\n";
+        }
+        output
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::json!({
+            "file": self.file,
+            "errors": self.error_count(),
+            "warnings": self.warning_count(),
+            "diagnostics": self.diagnostics,
+        })
+        .to_string()
+    }
+}
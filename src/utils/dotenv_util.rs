@@ -0,0 +1,198 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+
+// Reads a .env file. "${NAME}" in unquoted and double-quoted values refers to keys defined earlier in
+// the file, then to the inherited variables, then to ldot's own environment.
+pub fn load_env_file(path: &Path, inherited: &BTreeMap<String, String>) -> Result<BTreeMap<String, String>, String> {
+    let content = match std::fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(e) => return Err(format!("Could not read env file: {} {}", path.display(), e)),
+    };
+    parse_env(&content, inherited).map_err(|e| format!("{} in env file: {}", e, path.display()))
+}
+
+// Parses dotenv syntax: KEY=value lines, an optional "export " prefix, # comments,
+// 'single quoted' literal values and "double quoted" values with escapes that may span lines.
+pub fn parse_env(content: &str, inherited: &BTreeMap<String, String>) -> Result<BTreeMap<String, String>, String> {
+    let mut values: BTreeMap<String, String> = BTreeMap::new();
+    let mut lines = content.lines().enumerate();
+
+    while let Some((index, line)) = lines.next() {
+        let line_number = index + 1;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let line = line.strip_prefix("export ").unwrap_or(line).trim_start();
+        let (key, raw_value) = match line.split_once('=') {
+            Some((key, raw_value)) => (key.trim(), raw_value.trim_start()),
+            None => return Err(format!("Expected KEY=value on line {}", line_number)),
+        };
+        if key.is_empty() || !key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.') {
+            return Err(format!("Invalid key \"{}\" on line {}", key, line_number));
+        }
+
+        let value = if let Some(quoted) = raw_value.strip_prefix('\'') {
+            match quoted.find('\'') {
+                Some(end) => quoted[..end].to_string(),
+                None => return Err(format!("Unterminated single quote on line {}", line_number)),
+            }
+        } else if let Some(quoted) = raw_value.strip_prefix('"') {
+            // Double-quoted values may continue on the following lines
+            let mut quoted = quoted.to_string();
+            let end = loop {
+                if let Some(end) = find_closing_quote(&quoted) {
+                    break end;
+                }
+                match lines.next() {
+                    Some((_, next_line)) => {
+                        quoted.push('\n');
+                        quoted.push_str(next_line);
+                    }
+                    None => return Err(format!("Unterminated double quote on line {}", line_number)),
+                }
+            };
+            unescape_and_expand(&quoted[..end], &values, inherited)
+        } else {
+            let unquoted = match raw_value.find(" #") {
+                Some(comment) => &raw_value[..comment],
+                None => raw_value,
+            };
+            expand_references(unquoted.trim_end(), &values, inherited)
+        };
+        values.insert(key.to_string(), value);
+    }
+    Ok(values)
+}
+
+fn find_closing_quote(quoted: &str) -> Option<usize> {
+    let mut escaped = false;
+    for (index, c) in quoted.char_indices() {
+        match c {
+            '\\' if !escaped => escaped = true,
+            '"' if !escaped => return Some(index),
+            _ => escaped = false,
+        }
+    }
+    None
+}
+
+// Handles backslash escapes and "${NAME}" references of a double-quoted value in one pass, so "\${" stays literal
+fn unescape_and_expand(value: &str, values: &BTreeMap<String, String>, inherited: &BTreeMap<String, String>) -> String {
+    let mut result = String::new();
+    let mut literal = String::new();
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            literal.push(c);
+            continue;
+        }
+        result.push_str(&expand_references(&literal, values, inherited));
+        literal.clear();
+        match chars.next() {
+            Some('n') => result.push('\n'),
+            Some('t') => result.push('\t'),
+            Some('r') => result.push('\r'),
+            Some(other) => result.push(other),
+            None => result.push('\\'),
+        }
+    }
+    result.push_str(&expand_references(&literal, values, inherited));
+    result
+}
+
+// Expands "${NAME}", unknown names expand to an empty string like in a shell
+fn expand_references(value: &str, values: &BTreeMap<String, String>, inherited: &BTreeMap<String, String>) -> String {
+    let mut result = String::new();
+    let mut rest = value;
+    while let Some(start) = rest.find("${") {
+        let end = match rest[start..].find('}') {
+            Some(end) => start + end,
+            None => break,
+        };
+        result.push_str(&rest[..start]);
+        let name = &rest[start + 2..end];
+        let resolved = values
+            .get(name)
+            .or_else(|| inherited.get(name))
+            .cloned()
+            .or_else(|| std::env::var(name).ok())
+            .unwrap_or_default();
+        result.push_str(&resolved);
+        rest = &rest[end + 1..];
+    }
+    result.push_str(rest);
+    result
+}
+
+// Formats a variable as a KEY=value line that parse_env reads back unchanged
+pub fn format_env_line(key: &str, value: &str) -> String {
+    let is_plain = !value.is_empty()
+        && value
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "_-./:@,+%".contains(c));
+    if is_plain {
+        format!("{}={}", key, value)
+    } else {
+        let escaped = value
+            .replace('\\', "\\\\")
+            .replace('"', "\\\"")
+            .replace('\n', "\\n")
+            .replace('\t', "\\t")
+            .replace('$', "\\$");
+        format!("{}=\"{}\"", key, escaped)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(content: &str) -> BTreeMap<String, String> {
+        parse_env(content, &BTreeMap::new()).unwrap()
+    }
+
+    #[test]
+    fn quoting() {
+        let values = parse("PLAIN=a b\nSINGLE='${PLAIN} \\n'\nDOUBLE=\"${PLAIN} \\\"x\\\"\"\nESCAPED=\"\\${PLAIN}\"\n");
+        assert_eq!(values["PLAIN"], "a b");
+        assert_eq!(values["SINGLE"], "${PLAIN} \\n");
+        assert_eq!(values["DOUBLE"], "a b \"x\"");
+        assert_eq!(values["ESCAPED"], "${PLAIN}");
+        assert!(parse_env("A='open", &BTreeMap::new()).is_err());
+        assert!(parse_env("A=\"open\nB=1", &BTreeMap::new()).is_err());
+    }
+
+    #[test]
+    fn export_prefix() {
+        let values = parse("export A=1\nexport   B=\"2\"\n");
+        assert_eq!(values["A"], "1");
+        assert_eq!(values["B"], "2");
+    }
+
+    #[test]
+    fn comments() {
+        let values = parse("# a comment\n  # indented\nA=1 # trailing\nB=\"2 # kept\"\nC='3 # kept'\nD=url#fragment\n");
+        assert_eq!(values.len(), 4);
+        assert_eq!(values["A"], "1");
+        assert_eq!(values["B"], "2 # kept");
+        assert_eq!(values["C"], "3 # kept");
+        assert_eq!(values["D"], "url#fragment");
+    }
+
+    #[test]
+    fn escaped_newlines() {
+        let values = parse("ESCAPED=\"one\\ntwo\\tthree\"\nSPANNING=\"one\ntwo\"\nAFTER=${SPANNING}\n");
+        assert_eq!(values["ESCAPED"], "one\ntwo\tthree");
+        assert_eq!(values["SPANNING"], "one\ntwo");
+        assert_eq!(values["AFTER"], "one\ntwo");
+    }
+
+    #[test]
+    fn formatted_lines_read_back_unchanged() {
+        for value in ["plain", "", "with space", "quote \" and \\", "line\nbreak", "${NOT_EXPANDED}"] {
+            let line = format_env_line("KEY", value);
+            assert_eq!(parse(&line)["KEY"], value, "{}", line);
+        }
+    }
+}
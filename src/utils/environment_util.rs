@@ -0,0 +1,83 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use crate::models::stack_config_json::{ProjectConfig, ScriptConfig, StackConfig, StageConfig};
+use crate::utils::dotenv_util;
+use crate::utils::interpolation_util::{self, VariableScope};
+use crate::utils::stack_path_util;
+
+// Variables exported to every command, scripts and stages add LDOT_SCRIPT or LDOT_PROJECT and LDOT_STAGE
+pub fn get_ldot_environment(stack_config: &StackConfig, stack_file: &str) -> BTreeMap<String, String> {
+    let mut env: BTreeMap<String, String> = BTreeMap::new();
+    env.insert("LDOT_STACK".to_string(), stack_config.stack_name.to_string());
+    env.insert("LDOT_STACK_FILE".to_string(), stack_file.to_string());
+    env
+}
+
+// Applies one level of "env_files" and "env". Files are read first, so "env" wins within a level.
+// Relative env files are resolved against the given directory.
+fn apply_env_level(
+    env: &mut BTreeMap<String, String>,
+    env_files: &[String],
+    env_values: &BTreeMap<String, String>,
+    directory: &Path,
+    scope: &VariableScope,
+) -> Result<(), String> {
+    for env_file in env_files {
+        let env_file = directory.join(interpolation_util::interpolate(env_file, scope)?);
+        let file_values = dotenv_util::load_env_file(&env_file, env)?;
+        env.extend(file_values);
+    }
+    for (key, value) in env_values {
+        env.insert(key.to_string(), interpolation_util::interpolate(value, scope)?);
+    }
+    Ok(())
+}
+
+// The environment of a stage, from lowest to highest precedence: stack, project, stage, then the LDOT_* variables.
+// Without a stage, the project's environment is returned.
+pub fn get_stage_environment(
+    stack_config: &StackConfig,
+    stack_file: &str,
+    project: &ProjectConfig,
+    stage: Option<&StageConfig>,
+) -> Result<BTreeMap<String, String>, String> {
+    let stage_name = stage.map(|stage| stage.stage_name.as_str()).unwrap_or("");
+    let scope = interpolation_util::get_stage_scope(stack_config, stack_file, project, stage_name);
+    let root_directory = stack_path_util::get_root_directory(stack_config, stack_file);
+    let project_directory = stack_path_util::get_project_directory(stack_config, stack_file, project);
+
+    let mut env: BTreeMap<String, String> = BTreeMap::new();
+    apply_env_level(&mut env, &stack_config.env_files, &stack_config.env, &root_directory, &scope)?;
+    apply_env_level(&mut env, &project.env_files, &project.env, &project_directory, &scope)?;
+    if let Some(stage) = stage {
+        apply_env_level(&mut env, &stage.env_files, &stage.env, &project_directory, &scope)?;
+    }
+
+    env.extend(get_ldot_environment(stack_config, stack_file));
+    env.insert("LDOT_PROJECT".to_string(), project.project_name.to_string());
+    if let Some(stage) = stage {
+        env.insert("LDOT_STAGE".to_string(), stage.stage_name.to_string());
+    }
+    Ok(env)
+}
+
+// The environment of a script, from lowest to highest precedence: stack, script, then the LDOT_* variables.
+// The script's own level is interpolated with its scope, so it can use "${args}" and "${params.NAME}".
+pub fn get_script_environment(
+    stack_config: &StackConfig,
+    stack_file: &str,
+    script: &ScriptConfig,
+    script_scope: &VariableScope,
+) -> Result<BTreeMap<String, String>, String> {
+    let stack_scope = interpolation_util::get_stack_scope(stack_config, stack_file);
+    let root_directory = stack_path_util::get_root_directory(stack_config, stack_file);
+
+    let mut env: BTreeMap<String, String> = BTreeMap::new();
+    apply_env_level(&mut env, &stack_config.env_files, &stack_config.env, &root_directory, &stack_scope)?;
+    apply_env_level(&mut env, &script.env_files, &script.env, &root_directory, script_scope)?;
+
+    env.extend(get_ldot_environment(stack_config, stack_file));
+    env.insert("LDOT_SCRIPT".to_string(), script.script_name.to_string());
+    Ok(env)
+}
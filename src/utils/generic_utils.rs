@@ -1,7 +1,9 @@
-use std::{path::{PathBuf, Path}, env, fs::File};
+use std::{path::{PathBuf, Path}, env, fs::File, time::Duration};
+
+use colored::Colorize;
 
 pub fn get_current_working_dir() -> std::io::Result<PathBuf> {
-    return env::current_dir()
+    env::current_dir()
 }
 
 pub fn get_file_name_check_if_parent_dir_exists() -> String {
@@ -9,12 +11,12 @@ pub fn get_file_name_check_if_parent_dir_exists() -> String {
     let mut error = true;
     while error {
         get_line_from_console_allow_blank();
-        if filename == "" {
+        if filename.is_empty() {
             return filename;
         }
         let mut path = Path::new(&filename);
         if path.is_dir() {
-            filename = filename + "/ldot_stack.json";
+            filename += "/ldot_stack.json";
             path = Path::new(&filename);
             println!("{:?} is a directory, appending default ldot stack file name. ({})",filename.replace("//", "/"), path.to_string_lossy().replace("//", "/"));
         }
@@ -24,24 +26,129 @@ pub fn get_file_name_check_if_parent_dir_exists() -> String {
             println!("File already exists, use: ldot load \"{}\"", path.to_string_lossy());
         }
         
-        let _file = match File::create(&path) {
+        match File::create(path) {
             Ok(file) => { drop(file);
                 error = false;
             }
                 ,
             Err(e) => {
-                println!("Error creating file. {}", e.to_string());
+                println!("Error creating file. {}", e);
                 // std::process::exit(2);
             },
         };
         filename = "".to_string();
     }
-    return filename;
+    filename
 }
 
 pub fn get_line_from_console_allow_blank() -> String {
     let mut input = String::new();
     std::io::stdin().read_line(&mut input).unwrap();
     input = input.replace("\n", "");
-    return input;
-}
\ No newline at end of file
+    input
+}
+
+// Parses durations such as "500ms", "90s", "5m" or "1h". A bare number is read as seconds.
+pub fn parse_duration(duration: &str) -> Result<Duration, String> {
+    let duration = duration.trim();
+    let unit_start = duration
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(duration.len());
+    let (amount, unit) = duration.split_at(unit_start);
+    let amount: f64 = match amount.parse() {
+        Ok(amount) => amount,
+        Err(_) => return Err(format!("Invalid duration: {}", duration)),
+    };
+    let seconds = match unit.trim() {
+        "ms" => amount / 1000.0,
+        "" | "s" => amount,
+        "m" => amount * 60.0,
+        "h" => amount * 3600.0,
+        _ => return Err(format!("Invalid duration unit, expected ms, s, m or h: {}", duration)),
+    };
+    Duration::try_from_secs_f64(seconds).map_err(|_| format!("Duration is too long: {}", duration))
+}
+
+// A unified diff of two versions of a file, with added lines in green and removed lines in red
+pub fn get_unified_diff(old: &str, new: &str, file_name: &str) -> String {
+    let diff = similar::TextDiff::from_lines(old, new);
+    let mut output = String::new();
+    for line in diff
+        .unified_diff()
+        .header(file_name, file_name)
+        .to_string()
+        .lines()
+    {
+        let line = if line.starts_with('+') && !line.starts_with("+++") {
+            line.green().to_string()
+        } else if line.starts_with('-') && !line.starts_with("---") {
+            line.red().to_string()
+        } else {
+            line.to_string()
+        };
+        output += &line;
+        output += "\n";
+    }
+    output
+}
+
+// Matches a name against a pattern where "*" is any run of characters and "?" is a single character
+pub fn matches_glob(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    let (mut pattern_index, mut name_index) = (0, 0);
+    // The last "*" and the position in the name it currently covers up to
+    let mut backtrack: Option<(usize, usize)> = None;
+    while name_index < name.len() {
+        match pattern.get(pattern_index) {
+            Some('*') => {
+                backtrack = Some((pattern_index, name_index));
+                pattern_index += 1;
+            }
+            Some(c) if *c == '?' || *c == name[name_index] => {
+                pattern_index += 1;
+                name_index += 1;
+            }
+            _ => match backtrack {
+                Some((star_index, covered)) => {
+                    backtrack = Some((star_index, covered + 1));
+                    pattern_index = star_index + 1;
+                    name_index = covered + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[pattern_index..].iter().all(|c| *c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn durations_with_units() {
+        assert_eq!(parse_duration("500ms"), Ok(Duration::from_millis(500)));
+        assert_eq!(parse_duration("90s"), Ok(Duration::from_secs(90)));
+        assert_eq!(parse_duration("90"), Ok(Duration::from_secs(90)));
+        assert_eq!(parse_duration(" 1.5 m "), Ok(Duration::from_secs(90)));
+        assert_eq!(parse_duration("5m"), Ok(Duration::from_secs(300)));
+        assert_eq!(parse_duration("1h"), Ok(Duration::from_secs(3600)));
+    }
+
+    #[test]
+    fn zero_durations() {
+        assert_eq!(parse_duration("0"), Ok(Duration::ZERO));
+        assert_eq!(parse_duration("0ms"), Ok(Duration::ZERO));
+    }
+
+    #[test]
+    fn invalid_durations() {
+        assert_eq!(parse_duration(""), Err("Invalid duration: ".to_string()));
+        assert_eq!(parse_duration("soon"), Err("Invalid duration: soon".to_string()));
+        assert_eq!(parse_duration("-5s"), Err("Invalid duration: -5s".to_string()));
+        assert_eq!(parse_duration("1.2.3s"), Err("Invalid duration: 1.2.3s".to_string()));
+        assert!(parse_duration("5d").unwrap_err().starts_with("Invalid duration unit"));
+        assert!(parse_duration("100000000000000000000000h").unwrap_err().starts_with("Duration is too long"));
+    }
+}
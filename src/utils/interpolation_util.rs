@@ -0,0 +1,361 @@
+use std::collections::BTreeMap;
+
+use crate::models::stack_config_json::{CommandConfig, CommandDetails, ProjectConfig, ScriptConfig, StackConfig};
+use crate::utils::{param_util, stack_path_util};
+
+enum TemplatePart {
+    Literal(String),
+    Reference(String),
+}
+
+// Values available to "${...}" references. Built-in values such as "stack.dir" and the stack's own
+// "variables" live side by side, "${env.NAME}" is read from ldot's environment when it is resolved.
+#[derive(Debug, Clone, Default)]
+pub struct VariableScope {
+    values: BTreeMap<String, String>,
+    // Values quoted for the shell, they replace the plain ones in "run" strings
+    quoted: BTreeMap<String, String>,
+}
+
+impl VariableScope {
+    pub fn new() -> VariableScope {
+        VariableScope {
+            values: BTreeMap::new(),
+            quoted: BTreeMap::new(),
+        }
+    }
+
+    pub fn set(&mut self, name: &str, value: &str) {
+        self.values.insert(name.to_string(), value.to_string());
+    }
+
+    // Sets a value that is used as given, a "${" in it is not a reference
+    pub fn set_literal(&mut self, name: &str, value: &str) {
+        self.set(name, &value.replace("${", "$${"));
+    }
+
+    // Sets a value that is used as given, and quoted in "run" strings so it arrives as one argument however it is
+    // written. "env" values and "cwd" don't go through a shell and get it unquoted.
+    pub fn set_quoted(&mut self, name: &str, value: &str) {
+        self.set_literal(name, value);
+        self.quoted.insert(name.to_string(), shell_words::quote(value).replace("${", "$${"));
+    }
+
+    // Sets "${args}" to the arguments forwarded from the command line. "run" strings get them quoted so the shell
+    // passes each one on as given, elsewhere they are separated by spaces.
+    pub fn set_arguments(&mut self, args: &[String]) {
+        self.set_literal("args", &args.join(" "));
+        self.quoted.insert("args".to_string(), shell_words::join(args).replace("${", "$${"));
+    }
+
+    // The scope "run" strings are interpolated with, quoted values replace the plain ones
+    fn for_shell(&self) -> VariableScope {
+        let mut scope = self.clone();
+        scope.values.extend(self.quoted.clone());
+        scope
+    }
+
+    // Checks that a reference can be resolved without reading the environment, used by validation
+    fn check(&self, name: &str, resolving: &mut Vec<String>) -> Result<(), String> {
+        if name.starts_with("env.") {
+            return Ok(());
+        }
+        let value = match self.values.get(name) {
+            Some(value) => value,
+            None => return Err(format!("Undefined variable: ${{{}}}", name)),
+        };
+        if resolving.iter().any(|entry| entry == name) {
+            resolving.push(name.to_string());
+            return Err(format!("Variable references itself: {}", resolving.join(" -> ")));
+        }
+        resolving.push(name.to_string());
+        for reference in get_references(value)? {
+            self.check(&reference, resolving)?;
+        }
+        resolving.pop();
+        Ok(())
+    }
+
+    fn resolve(&self, name: &str, resolving: &mut Vec<String>) -> Result<String, String> {
+        if let Some(env_name) = name.strip_prefix("env.") {
+            return match std::env::var(env_name) {
+                Ok(value) => Ok(value),
+                Err(_) => Err(format!("Environment variable is not set: {}", env_name)),
+            };
+        }
+        let value = match self.values.get(name) {
+            Some(value) => value,
+            None => return Err(format!("Undefined variable: ${{{}}}", name)),
+        };
+        if resolving.iter().any(|entry| entry == name) {
+            resolving.push(name.to_string());
+            return Err(format!("Variable references itself: {}", resolving.join(" -> ")));
+        }
+        // Variables may reference other variables
+        resolving.push(name.to_string());
+        let resolved = interpolate_with(value, self, resolving);
+        resolving.pop();
+        resolved
+    }
+}
+
+// Splits a template into literal text and "${...}" references. "$${" is an escaped, literal "${".
+fn parse_template(template: &str) -> Result<Vec<TemplatePart>, String> {
+    let mut parts: Vec<TemplatePart> = vec![];
+    let mut literal = String::new();
+    let mut rest = template;
+    while let Some(start) = rest.find("${") {
+        if rest[..start].ends_with('$') {
+            literal.push_str(&rest[..start - 1]);
+            literal.push_str("${");
+            rest = &rest[start + 2..];
+            continue;
+        }
+        literal.push_str(&rest[..start]);
+        let end = match rest[start..].find('}') {
+            Some(end) => start + end,
+            None => return Err(format!("Unterminated \"${{\" in: {}", template)),
+        };
+        let name = rest[start + 2..end].trim();
+        if name.is_empty() {
+            return Err(format!("Empty \"${{}}\" in: {}", template));
+        }
+        if !literal.is_empty() {
+            parts.push(TemplatePart::Literal(literal));
+            literal = String::new();
+        }
+        parts.push(TemplatePart::Reference(name.to_string()));
+        rest = &rest[end + 1..];
+    }
+    literal.push_str(rest);
+    if !literal.is_empty() {
+        parts.push(TemplatePart::Literal(literal));
+    }
+    Ok(parts)
+}
+
+pub fn interpolate(template: &str, scope: &VariableScope) -> Result<String, String> {
+    interpolate_with(template, scope, &mut vec![])
+}
+
+fn interpolate_with(template: &str, scope: &VariableScope, resolving: &mut Vec<String>) -> Result<String, String> {
+    let mut result = String::new();
+    for part in parse_template(template)? {
+        match part {
+            TemplatePart::Literal(literal) => result.push_str(&literal),
+            TemplatePart::Reference(name) => result.push_str(&scope.resolve(&name, resolving)?),
+        }
+    }
+    Ok(result)
+}
+
+// Checks every reference of a template against the scope, without resolving environment variables
+pub fn check_template(template: &str, scope: &VariableScope) -> Result<(), String> {
+    for reference in get_references(template)? {
+        scope.check(&reference, &mut vec![])?;
+    }
+    Ok(())
+}
+
+// Returns the names referenced by a template, e.g. ["project.path", "api_port"]
+pub fn get_references(template: &str) -> Result<Vec<String>, String> {
+    Ok(parse_template(template)?
+        .into_iter()
+        .filter_map(|part| match part {
+            TemplatePart::Reference(name) => Some(name),
+            TemplatePart::Literal(_) => None,
+        })
+        .collect())
+}
+
+// Every string of a command that is interpolated: "run", "cwd" and the "env" values
+pub fn get_command_templates(command: &CommandConfig) -> Vec<String> {
+    let details = command.to_details();
+    let mut templates = vec![details.run];
+    templates.extend(details.cwd);
+    templates.extend(details.env.into_values());
+    templates
+}
+
+pub fn interpolate_command(command: &CommandConfig, scope: &VariableScope) -> Result<CommandConfig, String> {
+    let details = command.to_details();
+    let mut env: BTreeMap<String, String> = BTreeMap::new();
+    for (key, value) in &details.env {
+        env.insert(key.to_string(), interpolate(value, scope)?);
+    }
+    let cwd = match &details.cwd {
+        Some(cwd) => Some(interpolate(cwd, scope)?),
+        None => None,
+    };
+    let run = interpolate(&details.run, &scope.for_shell())?;
+    Ok(match command {
+        CommandConfig::Simple(_) => CommandConfig::Simple(run),
+        CommandConfig::Detailed(_) => CommandConfig::Detailed(CommandDetails {
+            run,
+            cwd,
+            env,
+            ..details
+        }),
+    })
+}
+
+// Commands use the arguments forwarded from the command line as "${args}", or as "$@" in shells that take
+// arguments. When none of the commands does, the arguments are added to the end of the last one.
+pub fn forward_arguments(commands: &[CommandConfig], args: &[String], takes_arguments: bool) -> Vec<CommandConfig> {
+    let mut commands: Vec<CommandConfig> = commands.to_vec();
+    let uses_arguments = commands.iter().any(|command| {
+        let run = command.to_details().run;
+        (takes_arguments && (run.contains("$@") || run.contains("$*")))
+            || get_references(&run).is_ok_and(|references| references.iter().any(|name| name == "args"))
+    });
+    if !uses_arguments && !args.is_empty() {
+        if let Some(command) = commands.last_mut() {
+            *command = command.with_run(format!("{} ${{args}}", command.to_details().run));
+        }
+    }
+    commands
+}
+
+// Variables available everywhere in a stack: its "variables" plus "stack.name", "stack.dir", "stack.root" and "stack.file"
+pub fn get_stack_scope(stack_config: &StackConfig, stack_file: &str) -> VariableScope {
+    let mut scope = VariableScope::new();
+    for (name, value) in &stack_config.variables {
+        scope.set(name, value);
+    }
+    scope.set("stack.name", &stack_config.stack_name);
+    scope.set("stack.file", stack_file);
+    scope.set(
+        "stack.dir",
+        &stack_path_util::get_stack_directory(stack_file).display().to_string(),
+    );
+    scope.set(
+        "stack.root",
+        &stack_path_util::get_root_directory(stack_config, stack_file).display().to_string(),
+    );
+    scope
+}
+
+// Adds "project.name", "project.path" and "stage.name" to the stack's variables
+pub fn get_stage_scope(
+    stack_config: &StackConfig,
+    stack_file: &str,
+    project: &ProjectConfig,
+    stage_name: &str,
+) -> VariableScope {
+    let mut scope = get_stack_scope(stack_config, stack_file);
+    scope.set("project.name", &project.project_name);
+    scope.set(
+        "project.path",
+        &stack_path_util::get_project_directory(stack_config, stack_file, project).display().to_string(),
+    );
+    scope.set("stage.name", stage_name);
+    scope.set_arguments(&[]);
+    scope
+}
+
+// Adds "${args}" and a "${params.NAME}" for each parameter of the script to the stack's variables. Parameters are
+// set to their defaults, or left empty when they have none, and quoted in "run" strings like "${args}".
+pub fn get_script_scope(stack_config: &StackConfig, stack_file: &str, script: &ScriptConfig) -> VariableScope {
+    let mut scope = get_stack_scope(stack_config, stack_file);
+    scope.set_arguments(&[]);
+    for param in &script.params {
+        let default = param_util::get_default_value(param).ok().flatten().unwrap_or_default();
+        scope.set_quoted(&format!("params.{}", param.param_name), &default);
+    }
+    scope
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn commands(runs: &[&str]) -> Vec<CommandConfig> {
+        runs.iter().map(|run| CommandConfig::Simple(run.to_string())).collect()
+    }
+
+    fn runs(commands: &[CommandConfig]) -> Vec<String> {
+        commands.iter().map(|command| command.to_details().run).collect()
+    }
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn forward_arguments_through_args_variable() {
+        let forwarded = forward_arguments(&commands(&["echo ${args}", "echo done"]), &args(&["a b"]), true);
+        assert_eq!(runs(&forwarded), vec!["echo ${args}", "echo done"]);
+    }
+
+    #[test]
+    fn forward_arguments_through_positionals() {
+        let forwarded = forward_arguments(&commands(&["printf '[%s]' \"$@\"", "echo done"]), &args(&["a b"]), true);
+        assert_eq!(runs(&forwarded), vec!["printf '[%s]' \"$@\"", "echo done"]);
+    }
+
+    #[test]
+    fn forward_arguments_appends_when_unused() {
+        let forwarded = forward_arguments(&commands(&["echo first", "echo last"]), &args(&["a b"]), true);
+        assert_eq!(runs(&forwarded), vec!["echo first", "echo last ${args}"]);
+        // Without positionals, "$@" doesn't receive the arguments
+        let forwarded = forward_arguments(&commands(&["echo \"$@\""]), &args(&["a b"]), false);
+        assert_eq!(runs(&forwarded), vec!["echo \"$@\" ${args}"]);
+        let forwarded = forward_arguments(&commands(&["echo last"]), &[], true);
+        assert_eq!(runs(&forwarded), vec!["echo last"]);
+    }
+
+    #[test]
+    fn nested_references_are_resolved() {
+        let mut scope = VariableScope::new();
+        scope.set("host", "localhost");
+        scope.set("port", "8080");
+        scope.set("url", "http://${host}:${port}");
+        scope.set("health", "${url}/health");
+        assert_eq!(interpolate("curl ${health}", &scope).unwrap(), "curl http://localhost:8080/health");
+        scope.set("a", "${b}");
+        scope.set("b", "${a}");
+        let err = interpolate("${a}", &scope).unwrap_err();
+        assert_eq!(err, "Variable references itself: a -> b -> a");
+        assert_eq!(check_template("${a}", &scope).unwrap_err(), err);
+    }
+
+    #[test]
+    fn escaped_references_stay_literal() {
+        let mut scope = VariableScope::new();
+        scope.set("name", "api");
+        assert_eq!(interpolate("$${name} is ${name}", &scope).unwrap(), "${name} is api");
+        assert_eq!(get_references("$${name} ${other}").unwrap(), vec!["other"]);
+    }
+
+    #[test]
+    fn unknown_variables_are_errors() {
+        let scope = VariableScope::new();
+        assert_eq!(interpolate("echo ${missing}", &scope).unwrap_err(), "Undefined variable: ${missing}");
+        assert_eq!(check_template("echo ${missing}", &scope).unwrap_err(), "Undefined variable: ${missing}");
+        assert!(check_template("echo ${env.ANY_NAME}", &scope).is_ok());
+        assert!(interpolate("echo ${missing", &scope).unwrap_err().starts_with("Unterminated"));
+        assert!(interpolate("echo ${}", &scope).unwrap_err().starts_with("Empty"));
+    }
+
+    #[test]
+    fn literal_and_quoted_values() {
+        let mut scope = VariableScope::new();
+        scope.set("name", "api");
+        scope.set_literal("literal", "${name} as written");
+        scope.set_quoted("quoted", "hello ${name}");
+        assert_eq!(interpolate("${literal}", &scope).unwrap(), "${name} as written");
+        assert_eq!(interpolate("${quoted}", &scope).unwrap(), "hello ${name}");
+        assert_eq!(interpolate("${quoted}", &scope.for_shell()).unwrap(), "'hello ${name}'");
+
+        let command = CommandConfig::Detailed(CommandDetails {
+            run: "echo ${quoted}".to_string(),
+            cwd: Some("${quoted}".to_string()),
+            env: BTreeMap::from([("MESSAGE".to_string(), "${quoted}".to_string())]),
+            ..Default::default()
+        });
+        let details = interpolate_command(&command, &scope).unwrap().to_details();
+        assert_eq!(details.run, "echo 'hello ${name}'");
+        assert_eq!(details.cwd.as_deref(), Some("hello ${name}"));
+        assert_eq!(details.env["MESSAGE"], "hello ${name}");
+    }
+}
@@ -0,0 +1,335 @@
+use std::collections::HashMap;
+
+use crate::utils::stack_format_util;
+
+// A 1-based position in a source file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SourceLocation {
+    pub line: usize,
+    pub column: usize,
+}
+
+// Appends an object key or array index to a path
+pub fn join_path(path: &str, segment: &str) -> String {
+    if path.is_empty() || segment.starts_with('[') {
+        format!("{}{}", path, segment)
+    } else {
+        format!("{}.{}", path, segment)
+    }
+}
+
+// Finds the location of a path, falling back to its closest parent for values that are missing from the file
+pub fn find_location(locations: &HashMap<String, SourceLocation>, path: &str) -> Option<SourceLocation> {
+    let mut path = path;
+    loop {
+        if let Some(location) = locations.get(path) {
+            return Some(*location);
+        }
+        if path.is_empty() {
+            return None;
+        }
+        path = match path.rfind(['.', '[']) {
+            Some(end) => &path[..end],
+            None => "",
+        };
+    }
+}
+
+// An open block mapping or sequence of a YAML document
+struct YamlContainer {
+    indent: usize,
+    path: String,
+    sequence: bool,
+    next_index: usize,
+}
+
+// Maps the paths of a YAML document to where their values start, like jsonc_util::get_value_locations. Only block
+// mappings and sequences are followed, values inside flow collections such as "[a, b]" are located at their key.
+pub fn get_yaml_value_locations(source: &str) -> HashMap<String, SourceLocation> {
+    let mut locations = HashMap::new();
+    let mut containers: Vec<YamlContainer> = vec![];
+    // A key or item without a value on its line, its value may follow on more indented lines
+    let mut pending: Option<(usize, String)> = None;
+    // The lines of a block scalar ("|" or ">") are indented more than its key
+    let mut block_indent: Option<usize> = None;
+    for (index, raw_line) in source.lines().enumerate() {
+        let raw_content = raw_line.trim_start();
+        if let Some(block) = block_indent {
+            if raw_content.is_empty() || raw_line.len() - raw_content.len() > block {
+                continue;
+            }
+            block_indent = None;
+        }
+        let line = match stack_format_util::find_comment_start(raw_line) {
+            Some(start) => &raw_line[..start],
+            None => raw_line,
+        };
+        let mut rest = line.trim();
+        if rest.is_empty() || rest.starts_with("---") || rest.starts_with("...") || rest.starts_with('%') {
+            continue;
+        }
+        let mut indent = line.len() - line.trim_start().len();
+        let location = |offset: usize| SourceLocation {
+            line: index + 1,
+            column: raw_line[..offset].chars().count() + 1,
+        };
+        loop {
+            let item = rest == "-" || rest.starts_with("- ");
+            if let Some((pending_indent, path)) = pending.take() {
+                if indent > pending_indent || (indent == pending_indent && item) {
+                    containers.push(YamlContainer { indent, path, sequence: item, next_index: 0 });
+                }
+            }
+            // Closes what is indented more, and a sequence at the indentation of its key once the mapping continues
+            while containers.last().is_some_and(|container| {
+                container.indent > indent || (container.indent == indent && container.sequence != item)
+            }) {
+                containers.pop();
+            }
+            if containers.is_empty() {
+                containers.push(YamlContainer { indent, path: String::new(), sequence: item, next_index: 0 });
+            }
+            let container = containers.last_mut().unwrap();
+            if container.indent != indent {
+                break;
+            }
+
+            let (path, value) = if item {
+                let path = format!("{}[{}]", container.path, container.next_index);
+                container.next_index += 1;
+                (path, &rest[1..])
+            } else {
+                match split_yaml_key(rest) {
+                    Some((key, value)) => (join_path(&container.path, &key), value),
+                    None => break,
+                }
+            };
+            let value = value.trim_start();
+            let value_indent = indent + rest.len() - value.len();
+            if value.is_empty() {
+                locations.insert(path.clone(), location(indent));
+                pending = Some((indent, path));
+                break;
+            }
+            locations.insert(path.clone(), location(value_indent));
+            let nested = item && (value == "-" || value.starts_with("- ") || split_yaml_key(value).is_some());
+            if !nested {
+                let indicators = |c: char| c.is_ascii_digit() || c == '+' || c == '-';
+                if value.starts_with(['|', '>']) && value[1..].chars().all(indicators) {
+                    block_indent = Some(indent);
+                }
+                break;
+            }
+            // An item that starts a mapping or sequence on its own line, e.g. "- project_name: api"
+            pending = Some((indent, path));
+            indent = value_indent;
+            rest = value;
+        }
+    }
+    locations
+}
+
+// Splits "key: value" into the key and what follows the colon
+fn split_yaml_key(content: &str) -> Option<(String, &str)> {
+    let separated = |value: &str| value.is_empty() || value.starts_with([' ', '\t']);
+    if let Some(quote) = content.chars().next().filter(|c| *c == '"' || *c == '\'') {
+        let end = content[1..].find(quote)? + 1;
+        let value = content[end + 1..].trim_start().strip_prefix(':')?;
+        return separated(value).then(|| (content[1..end].to_string(), value));
+    }
+    if content.starts_with(['[', '{']) {
+        return None;
+    }
+    let colon = content
+        .match_indices(':')
+        .map(|(colon, _)| colon)
+        .find(|colon| separated(&content[colon + 1..]))?;
+    Some((content[..colon].trim_end().to_string(), &content[colon + 1..]))
+}
+
+// Maps the paths of a TOML document to where their values start. Tables and arrays of tables are followed, values
+// inside inline tables and arrays are located at their key.
+pub fn get_toml_value_locations(source: &str) -> HashMap<String, SourceLocation> {
+    let mut locations = HashMap::new();
+    let mut table = String::new();
+    // How many tables each array of tables has so far, by its path
+    let mut table_arrays: HashMap<String, usize> = HashMap::new();
+    // Values that span lines: open brackets and braces, or the delimiter of a multi-line string
+    let mut depth = 0;
+    let mut string_end: Option<&str> = None;
+    for (index, line) in source.lines().enumerate() {
+        if let Some(end) = string_end {
+            if line.contains(end) {
+                string_end = None;
+            }
+            continue;
+        }
+        if depth > 0 {
+            depth += get_toml_bracket_depth(line);
+            continue;
+        }
+        let content = line.trim_start();
+        let offset = line.len() - content.len();
+        let location = |offset: usize| SourceLocation {
+            line: index + 1,
+            column: line[..offset].chars().count() + 1,
+        };
+
+        if content.starts_with('[') {
+            let array = content.starts_with("[[");
+            let header = content.trim_start_matches('[');
+            let header = &header[..find_toml_unquoted(header, ']').unwrap_or(header.len())];
+            let mut segments = split_toml_key(header);
+            let last = segments.pop().unwrap_or_default();
+            table = resolve_toml_path(&segments, &table_arrays);
+            table = join_path(&table, &last);
+            if array {
+                locations.entry(table.clone()).or_insert(location(offset));
+                let count = table_arrays.entry(table.clone()).or_insert(0);
+                *count += 1;
+                table = format!("{}[{}]", table, *count - 1);
+            }
+            locations.entry(table.clone()).or_insert(location(offset));
+            continue;
+        }
+
+        let equals = match find_toml_unquoted(content, '=') {
+            Some(equals) if !content.starts_with('#') => equals,
+            _ => continue,
+        };
+        let mut path = table.clone();
+        for segment in split_toml_key(&content[..equals]) {
+            path = join_path(&path, &segment);
+        }
+        let value = content[equals + 1..].trim_start();
+        let value_offset = line.len() - value.len();
+        locations.insert(path, location(value_offset));
+
+        for delimiter in ["\"\"\"", "'''"] {
+            if let Some(opened) = value.strip_prefix(delimiter) {
+                if !opened.contains(delimiter) {
+                    string_end = Some(delimiter);
+                }
+            }
+        }
+        if string_end.is_none() {
+            depth = get_toml_bracket_depth(value);
+        }
+    }
+    locations
+}
+
+// Follows arrays of tables to their last table, e.g. "projects" to "projects[2]" after three "[[projects]]" headers
+fn resolve_toml_path(segments: &[String], table_arrays: &HashMap<String, usize>) -> String {
+    let mut path = String::new();
+    for segment in segments {
+        path = join_path(&path, segment);
+        if let Some(count) = table_arrays.get(&path) {
+            path = format!("{}[{}]", path, count - 1);
+        }
+    }
+    path
+}
+
+// Splits a dotted key such as 'env."KEY.NAME"' into its keys
+fn split_toml_key(key: &str) -> Vec<String> {
+    let mut segments = vec![];
+    let mut segment = String::new();
+    let mut quote: Option<char> = None;
+    for c in key.chars() {
+        match quote {
+            Some(open) if c == open => quote = None,
+            Some(_) => segment.push(c),
+            None if c == '"' || c == '\'' => quote = Some(c),
+            None if c == '.' => segments.push(std::mem::take(&mut segment)),
+            None if c.is_whitespace() => {}
+            None => segment.push(c),
+        }
+    }
+    segments.push(segment);
+    segments
+}
+
+// The first "target" outside of quoted strings, before any comment
+fn find_toml_unquoted(text: &str, target: char) -> Option<usize> {
+    let mut quote: Option<char> = None;
+    let mut escaped = false;
+    for (index, c) in text.char_indices() {
+        match quote {
+            Some('"') if escaped => escaped = false,
+            Some('"') if c == '\\' => escaped = true,
+            Some(open) if c == open => quote = None,
+            Some(_) => {}
+            None if c == target => return Some(index),
+            None if c == '"' || c == '\'' => quote = Some(c),
+            None if c == '#' => return None,
+            None => {}
+        }
+    }
+    None
+}
+
+// How many more brackets and braces a line opens than it closes, outside of strings and comments
+fn get_toml_bracket_depth(line: &str) -> i32 {
+    let mut depth = 0;
+    let mut quote: Option<char> = None;
+    let mut escaped = false;
+    for c in line.chars() {
+        match quote {
+            Some('"') if escaped => escaped = false,
+            Some('"') if c == '\\' => escaped = true,
+            Some(open) if c == open => quote = None,
+            Some(_) => {}
+            None if c == '"' || c == '\'' => quote = Some(c),
+            None if c == '[' || c == '{' => depth += 1,
+            None if c == ']' || c == '}' => depth -= 1,
+            None if c == '#' => break,
+            None => {}
+        }
+    }
+    depth
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(locations: &HashMap<String, SourceLocation>, path: &str) -> Option<(usize, usize)> {
+        locations.get(path).map(|location| (location.line, location.column))
+    }
+
+    #[test]
+    fn yaml_locations() {
+        let source = "# demo\nstack_name: demo\nprojects:\n- project_name: api\n  stages:\n    \
+                      - stage_name: build\n      commands:\n        - |\n          echo key: value\n        \
+                      - echo b # two\n      prerequisites: [a, b]\nscripts: []\n";
+        let locations = get_yaml_value_locations(source);
+        assert_eq!(at(&locations, "stack_name"), Some((2, 13)));
+        assert_eq!(at(&locations, "projects[0]"), Some((4, 3)));
+        assert_eq!(at(&locations, "projects[0].project_name"), Some((4, 17)));
+        assert_eq!(at(&locations, "projects[0].stages[0].stage_name"), Some((6, 19)));
+        assert_eq!(at(&locations, "projects[0].stages[0].commands[0]"), Some((8, 11)));
+        assert_eq!(at(&locations, "projects[0].stages[0].commands[1]"), Some((10, 11)));
+        assert_eq!(at(&locations, "projects[0].stages[0].prerequisites"), Some((11, 22)));
+        assert_eq!(at(&locations, "scripts"), Some((12, 10)));
+        assert_eq!(at(&locations, "echo key"), None);
+        let prerequisite = find_location(&locations, "projects[0].stages[0].prerequisites[1]");
+        assert_eq!(prerequisite, Some(SourceLocation { line: 11, column: 22 }));
+    }
+
+    #[test]
+    fn toml_locations() {
+        let source = "stack_name = \"demo\"\nenv.\"A.B\" = \"x\"\n\n[[projects]]\nproject_name = \"api\"\n\n\
+                      [[projects.stages]]\nstage_name = \"build\"\ncommands = [\n  \"echo [\",\n]\n\n\
+                      [[projects]]\nproject_name = \"web\" # second\n[projects.env]\nKEY = '''\nname = x\n'''\n";
+        let locations = get_toml_value_locations(source);
+        assert_eq!(at(&locations, "stack_name"), Some((1, 14)));
+        assert_eq!(at(&locations, "env.A.B"), Some((2, 13)));
+        assert_eq!(at(&locations, "projects[0]"), Some((4, 1)));
+        assert_eq!(at(&locations, "projects[0].stages[0].stage_name"), Some((8, 14)));
+        assert_eq!(at(&locations, "projects[0].stages[0].commands"), Some((9, 12)));
+        assert_eq!(at(&locations, "projects[1].project_name"), Some((14, 16)));
+        assert_eq!(at(&locations, "projects[1].env.KEY"), Some((16, 7)));
+        assert_eq!(at(&locations, "projects[1].env.name"), None);
+    }
+}
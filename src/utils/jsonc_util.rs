@@ -0,0 +1,706 @@
+use std::collections::HashMap;
+use std::iter::Peekable;
+use std::str::Chars;
+
+use serde_json::Value;
+
+use crate::utils::json_source_util::{self, SourceLocation};
+
+const INDENT: &str = "    ";
+
+// Replaces // and /* */ comments with spaces and drops trailing commas. Every other character, including
+// newlines, stays at its position so parse errors and diagnostics still point at the right line and column.
+pub fn strip_comments(source: &str) -> String {
+    let mut output: Vec<char> = Vec::with_capacity(source.len());
+    let mut last_significant: Option<usize> = None;
+    let mut chars = source.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' | '\'' => {
+                output.push(c);
+                let mut escaped = false;
+                for inner in chars.by_ref() {
+                    output.push(inner);
+                    match inner {
+                        '\\' if !escaped => escaped = true,
+                        _ if inner == c && !escaped => break,
+                        _ => escaped = false,
+                    }
+                }
+                last_significant = Some(output.len() - 1);
+            }
+            '/' if chars.peek() == Some(&'/') => {
+                output.push(' ');
+                while let Some(inner) = chars.peek() {
+                    if *inner == '\n' {
+                        break;
+                    }
+                    output.push(' ');
+                    chars.next();
+                }
+            }
+            '/' if chars.peek() == Some(&'*') => {
+                output.push(' ');
+                output.push(' ');
+                chars.next();
+                let mut previous = ' ';
+                for inner in chars.by_ref() {
+                    output.push(if inner == '\n' { '\n' } else { ' ' });
+                    if previous == '*' && inner == '/' {
+                        break;
+                    }
+                    previous = inner;
+                }
+            }
+            '}' | ']' => {
+                if let Some(index) = last_significant {
+                    if output[index] == ',' {
+                        output[index] = ' ';
+                    }
+                }
+                output.push(c);
+                last_significant = Some(output.len() - 1);
+            }
+            _ => {
+                output.push(c);
+                if !c.is_whitespace() {
+                    last_significant = Some(output.len() - 1);
+                }
+            }
+        }
+    }
+    output.into_iter().collect()
+}
+
+// A JSON document that keeps its comments, blank lines and key order, so ldot can rewrite a hand-edited
+// stack file without losing what a human wrote. JSON5 keys and literals are kept as written.
+pub struct Document {
+    leading: Vec<String>,
+    root: Node,
+    trailing: Vec<String>,
+}
+
+enum Node {
+    Object(Vec<Member>, Vec<String>),
+    Array(Vec<Item>, Vec<String>),
+    Scalar(String),
+}
+
+struct Member {
+    key: String,
+    item: Item,
+}
+
+struct Item {
+    comments: Vec<String>,
+    blank_line_before: bool,
+    value: Node,
+    trailing_comment: Option<String>,
+}
+
+// Comments found between two tokens and whether an empty line separated them from what came before
+#[derive(Default)]
+struct Trivia {
+    comments: Vec<(String, usize)>,
+    newlines_after: usize,
+}
+
+impl Trivia {
+    fn append(&mut self, other: Trivia) {
+        if self.comments.is_empty() && other.comments.is_empty() {
+            self.newlines_after += other.newlines_after;
+        } else {
+            let mut comments = other.comments;
+            if let Some(first) = comments.first_mut() {
+                first.1 += self.newlines_after;
+            }
+            self.comments.extend(comments);
+            self.newlines_after = other.newlines_after;
+        }
+    }
+
+    // The first comment belongs to the previous item when it starts on the same line
+    fn take_trailing_comment(&mut self) -> Option<String> {
+        match self.comments.first() {
+            Some((_, 0)) => Some(self.comments.remove(0).0),
+            _ => None,
+        }
+    }
+
+    fn has_blank_line(&self) -> bool {
+        match self.comments.first() {
+            Some((_, newlines)) => *newlines > 1,
+            None => self.newlines_after > 1,
+        }
+    }
+
+    fn into_comments(self) -> Vec<String> {
+        self.comments.into_iter().map(|(comment, _)| comment).collect()
+    }
+}
+
+// Reads JSON with comments, trailing commas and JSON5 keys and literals, and records where every value starts
+struct Parser<'a> {
+    chars: Peekable<Chars<'a>>,
+    line: usize,
+    column: usize,
+    locations: HashMap<String, SourceLocation>,
+}
+
+pub fn parse_document(source: &str) -> Result<Document, String> {
+    let mut parser = Parser::new(source);
+    let leading = parser.trivia()?.into_comments();
+    let root = parser.value("")?;
+    let trailing = parser.trivia()?.into_comments();
+    if parser.chars.peek().is_some() {
+        return Err("Unexpected content after the document".to_string());
+    }
+    Ok(Document { leading, root, trailing })
+}
+
+// Maps the path of every value in a JSON, JSONC or JSON5 document, e.g. "projects[1].stages[0].stage_name", to
+// where the value starts. Parsing stops at the first syntax error, so invalid documents still map the values before it.
+pub fn get_value_locations(source: &str) -> HashMap<String, SourceLocation> {
+    let mut parser = Parser::new(source);
+    let _ = parser.trivia().and_then(|_| parser.value(""));
+    parser.locations
+}
+
+impl Parser<'_> {
+    fn new(source: &str) -> Parser<'_> {
+        Parser {
+            chars: source.chars().peekable(),
+            line: 1,
+            column: 1,
+            locations: HashMap::new(),
+        }
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.chars.next()?;
+        if c == '\n' {
+            self.line += 1;
+            self.column = 1;
+        } else {
+            self.column += 1;
+        }
+        Some(c)
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), String> {
+        match self.next() {
+            Some(c) if c == expected => Ok(()),
+            Some(c) => Err(format!("Expected '{}' but found '{}'", expected, c)),
+            None => Err(format!("Expected '{}' but the document ended", expected)),
+        }
+    }
+
+    fn trivia(&mut self) -> Result<Trivia, String> {
+        let mut trivia = Trivia::default();
+        let mut newlines = 0;
+        while let Some(c) = self.chars.peek().copied() {
+            if c == '\n' {
+                newlines += 1;
+                self.next();
+            } else if c.is_whitespace() {
+                self.next();
+            } else if c == '/' {
+                self.next();
+                let mut comment = String::from("/");
+                match self.next() {
+                    Some('/') => {
+                        comment.push('/');
+                        while let Some(inner) = self.chars.peek() {
+                            if *inner == '\n' {
+                                break;
+                            }
+                            comment.push(*inner);
+                            self.next();
+                        }
+                    }
+                    Some('*') => {
+                        comment.push('*');
+                        loop {
+                            match self.next() {
+                                Some('*') if self.chars.peek() == Some(&'/') => {
+                                    self.next();
+                                    comment.push_str("*/");
+                                    break;
+                                }
+                                Some(inner) => comment.push(inner),
+                                None => return Err("Unterminated comment".to_string()),
+                            }
+                        }
+                    }
+                    _ => return Err("Expected a comment after '/'".to_string()),
+                }
+                trivia.comments.push((comment.trim_end().to_string(), newlines));
+                newlines = 0;
+            } else {
+                break;
+            }
+        }
+        trivia.newlines_after = newlines;
+        Ok(trivia)
+    }
+
+    fn value(&mut self, path: &str) -> Result<Node, String> {
+        let location = SourceLocation {
+            line: self.line,
+            column: self.column,
+        };
+        self.locations.insert(path.to_string(), location);
+        match self.chars.peek() {
+            Some('{') => self.container('}', path),
+            Some('[') => self.container(']', path),
+            Some('"') | Some('\'') => Ok(Node::Scalar(self.string()?)),
+            Some(_) => Ok(Node::Scalar(self.word())),
+            None => Err("Expected a value but the document ended".to_string()),
+        }
+    }
+
+    // Strings are kept with their quotes and escapes exactly as written
+    fn string(&mut self) -> Result<String, String> {
+        let quote = self.next().unwrap_or('"');
+        let mut raw = String::from(quote);
+        let mut escaped = false;
+        loop {
+            match self.next() {
+                Some(c) => {
+                    raw.push(c);
+                    match c {
+                        '\\' if !escaped => escaped = true,
+                        _ if c == quote && !escaped => return Ok(raw),
+                        _ => escaped = false,
+                    }
+                }
+                None => return Err("Unterminated string".to_string()),
+            }
+        }
+    }
+
+    // Numbers, true, false, null and unquoted JSON5 keys
+    fn word(&mut self) -> String {
+        let mut raw = String::new();
+        while let Some(c) = self.chars.peek() {
+            if c.is_whitespace() || ",:{}[]/".contains(*c) {
+                break;
+            }
+            raw.push(*c);
+            self.next();
+        }
+        raw
+    }
+
+    fn container(&mut self, close: char, path: &str) -> Result<Node, String> {
+        self.next();
+        let mut members: Vec<Member> = vec![];
+        let mut items: Vec<Item> = vec![];
+        let mut pending = self.trivia()?;
+        // Nothing separates the opening bracket from the first item
+        if let Some(first) = pending.comments.first_mut() {
+            first.1 = first.1.min(1);
+        } else {
+            pending.newlines_after = pending.newlines_after.min(1);
+        }
+        loop {
+            if self.chars.peek() == Some(&close) {
+                self.next();
+                let dangling = pending.into_comments();
+                return Ok(match close {
+                    '}' => Node::Object(members, dangling),
+                    _ => Node::Array(items, dangling),
+                });
+            }
+            let key = if close == '}' {
+                let key = match self.chars.peek() {
+                    Some('"') | Some('\'') => self.string()?,
+                    _ => self.word(),
+                };
+                if key.is_empty() {
+                    return Err("Expected an object key".to_string());
+                }
+                pending.append(self.trivia()?);
+                self.expect(':')?;
+                pending.append(self.trivia()?);
+                Some(key)
+            } else {
+                None
+            };
+
+            let value_path = match &key {
+                Some(key) => json_source_util::join_path(path, &decode_key(key)),
+                None => json_source_util::join_path(path, &format!("[{}]", items.len())),
+            };
+            let value = self.value(&value_path)?;
+            let mut after = self.trivia()?;
+            if self.chars.peek() == Some(&',') {
+                self.next();
+                after.append(self.trivia()?);
+            } else if self.chars.peek() != Some(&close) {
+                return Err(format!("Expected ',' or '{}'", close));
+            }
+            let trailing_comment = after.take_trailing_comment();
+            let item = Item {
+                blank_line_before: pending.has_blank_line(),
+                comments: pending.into_comments(),
+                value,
+                trailing_comment,
+            };
+            match key {
+                Some(key) => members.push(Member { key, item }),
+                None => items.push(item),
+            }
+            pending = after;
+        }
+    }
+}
+
+// Decodes a key or scalar as written, JSON5 syntax included
+fn decode<T: serde::de::DeserializeOwned>(raw: &str) -> Option<T> {
+    json5::from_str(raw).ok()
+}
+
+fn decode_key(raw: &str) -> String {
+    if raw.starts_with('"') || raw.starts_with('\'') {
+        decode(raw).unwrap_or_else(|| raw.to_string())
+    } else {
+        raw.to_string()
+    }
+}
+
+fn to_node(value: &Value) -> Node {
+    match value {
+        Value::Object(values) => Node::Object(
+            values
+                .iter()
+                .map(|(key, value)| Member {
+                    key: Value::String(key.to_string()).to_string(),
+                    item: to_item(value),
+                })
+                .collect(),
+            vec![],
+        ),
+        Value::Array(values) => Node::Array(values.iter().map(to_item).collect(), vec![]),
+        _ => Node::Scalar(value.to_string()),
+    }
+}
+
+fn to_item(value: &Value) -> Item {
+    Item {
+        comments: vec![],
+        blank_line_before: false,
+        value: to_node(value),
+        trailing_comment: None,
+    }
+}
+
+// How keys of existing objects are ordered when a document is updated
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyOrder {
+    // Existing keys keep their place, new keys are appended
+    Keep,
+    // Keys follow the order of the new value, taking their comments with them
+    Canonical,
+}
+
+// Keys that name the items of an array, so a project, stage, script, param or profile keeps its comments when
+// the items before it are added, removed or reordered
+const NAME_KEYS: [&str; 5] = ["project_name", "stage_name", "script_name", "param_name", "profile_name"];
+
+// Applies the new value to the existing tree, keeping the comments of every key and item that still exists.
+// Removed keys are dropped. Unless the order is canonical, new null keys are left out of objects that already
+// exist to keep diffs small.
+fn merge(node: Node, value: &Value, key_order: KeyOrder) -> Node {
+    match (node, value) {
+        (Node::Object(members, dangling), Value::Object(values)) => {
+            let mut existing: Vec<Option<Member>> = members.into_iter().map(Some).collect();
+            let mut merged: Vec<Member> = vec![];
+            if key_order == KeyOrder::Keep {
+                for member in existing.iter_mut() {
+                    let key = decode_key(&member.as_ref().unwrap().key);
+                    if let Some(value) = values.get(&key) {
+                        let member = member.take().unwrap();
+                        merged.push(Member {
+                            key: member.key,
+                            item: merge_item(member.item, value, key_order),
+                        });
+                    }
+                }
+            }
+            for (key, value) in values {
+                if merged.iter().any(|member| decode_key(&member.key) == *key) {
+                    continue;
+                }
+                let found = existing
+                    .iter_mut()
+                    .find(|member| member.as_ref().is_some_and(|member| decode_key(&member.key) == *key))
+                    .and_then(|member| member.take());
+                match found {
+                    Some(member) => merged.push(Member {
+                        key: member.key,
+                        item: merge_item(member.item, value, key_order),
+                    }),
+                    None if key_order == KeyOrder::Keep && value.is_null() => {}
+                    None => merged.push(Member {
+                        key: Value::String(key.to_string()).to_string(),
+                        item: to_item(value),
+                    }),
+                }
+            }
+            Node::Object(merged, dangling)
+        }
+        (Node::Array(items, dangling), Value::Array(values)) => {
+            // Named items are matched by name, everything else by position
+            let mut existing: Vec<Option<Item>> = items.into_iter().map(Some).collect();
+            let merged = values
+                .iter()
+                .enumerate()
+                .map(|(index, value)| {
+                    let found = match get_item_name(value) {
+                        Some(name) => existing
+                            .iter_mut()
+                            .find(|item| {
+                                item.as_ref().is_some_and(|item| get_node_name(&item.value).as_ref() == Some(&name))
+                            })
+                            .and_then(|item| item.take()),
+                        None => existing.get_mut(index).and_then(|item| item.take()),
+                    };
+                    match found {
+                        Some(item) => merge_item(item, value, key_order),
+                        None => to_item(value),
+                    }
+                })
+                .collect();
+            Node::Array(merged, dangling)
+        }
+        (Node::Scalar(raw), value) if decode::<Value>(&raw).as_ref() == Some(value) => Node::Scalar(raw),
+        (_, value) => to_node(value),
+    }
+}
+
+fn get_item_name(value: &Value) -> Option<(&'static str, String)> {
+    let values = value.as_object()?;
+    NAME_KEYS
+        .iter()
+        .find_map(|key| Some((*key, values.get(*key)?.as_str()?.to_string())))
+}
+
+fn get_node_name(node: &Node) -> Option<(&'static str, String)> {
+    let Node::Object(members, _) = node else {
+        return None;
+    };
+    NAME_KEYS.iter().find_map(|key| {
+        let member = members.iter().find(|member| decode_key(&member.key) == *key)?;
+        match &member.item.value {
+            Node::Scalar(raw) => Some((*key, decode::<String>(raw)?)),
+            _ => None,
+        }
+    })
+}
+
+fn merge_item(item: Item, value: &Value, key_order: KeyOrder) -> Item {
+    Item {
+        value: merge(item.value, value, key_order),
+        ..item
+    }
+}
+
+impl Document {
+    pub fn new(value: &Value) -> Document {
+        Document {
+            leading: vec![],
+            root: to_node(value),
+            trailing: vec![],
+        }
+    }
+
+    pub fn update(self, value: &Value, key_order: KeyOrder) -> Document {
+        Document {
+            root: merge(self.root, value, key_order),
+            ..self
+        }
+    }
+
+    // Prints the document with four space indentation, one item per line, and its comments in place
+    pub fn print(&self) -> String {
+        let mut output = String::new();
+        for comment in &self.leading {
+            output += comment;
+            output += "\n";
+        }
+        print_node(&self.root, 0, &mut output);
+        output += "\n";
+        for comment in &self.trailing {
+            output += comment;
+            output += "\n";
+        }
+        output
+    }
+}
+
+fn print_node(node: &Node, depth: usize, output: &mut String) {
+    match node {
+        Node::Scalar(raw) => output.push_str(raw),
+        Node::Object(members, dangling) => {
+            let entries = members.iter().map(|member| (Some(member.key.as_str()), &member.item));
+            print_container(('{', '}'), entries.collect(), dangling, depth, output);
+        }
+        Node::Array(items, dangling) => {
+            let entries = items.iter().map(|item| (None, item));
+            print_container(('[', ']'), entries.collect(), dangling, depth, output);
+        }
+    }
+}
+
+fn print_container(
+    (open, close): (char, char),
+    entries: Vec<(Option<&str>, &Item)>,
+    dangling: &[String],
+    depth: usize,
+    output: &mut String,
+) {
+    output.push(open);
+    if entries.is_empty() && dangling.is_empty() {
+        output.push(close);
+        return;
+    }
+    output.push('\n');
+    let indent = INDENT.repeat(depth + 1);
+    for (index, (key, item)) in entries.iter().enumerate() {
+        if item.blank_line_before && index > 0 {
+            output.push('\n');
+        }
+        for comment in &item.comments {
+            output.push_str(&format!("{}{}\n", indent, comment));
+        }
+        output.push_str(&indent);
+        if let Some(key) = key {
+            output.push_str(&format!("{}: ", key));
+        }
+        print_node(&item.value, depth + 1, output);
+        if index + 1 < entries.len() {
+            output.push(',');
+        }
+        if let Some(comment) = &item.trailing_comment {
+            output.push_str(&format!(" {}", comment));
+        }
+        output.push('\n');
+    }
+    for comment in dangling {
+        output.push_str(&format!("{}{}\n", indent, comment));
+    }
+    output.push_str(&INDENT.repeat(depth));
+    output.push(close);
+}
+
+// Rewrites a JSON, JSONC or JSON5 source with a new value, keeping its comments and key order.
+// Sources that can't be parsed, such as an empty new file, are replaced by the pretty printed value.
+pub fn update_source(source: Option<&str>, value: &Value, key_order: KeyOrder) -> String {
+    match source.map(parse_document) {
+        Some(Ok(document)) => document.update(value, key_order).print(),
+        _ => Document::new(value).print(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn strip_comments_keeps_positions() {
+        let source = "{\n  // name\n  \"a\": 1, /* one\n two */ \"b\": 2\n}";
+        let stripped = strip_comments(source);
+        assert_eq!(stripped.len(), source.len());
+        assert_eq!(stripped.lines().count(), source.lines().count());
+        let value: Value = serde_json::from_str(&stripped).unwrap();
+        assert_eq!(value, json!({"a": 1, "b": 2}));
+    }
+
+    #[test]
+    fn trailing_commas_are_accepted() {
+        let source = "{\"a\": [1, 2,], \"b\": {\"c\": true,},}";
+        let value: Value = serde_json::from_str(&strip_comments(source)).unwrap();
+        assert_eq!(value, json!({"a": [1, 2], "b": {"c": true}}));
+        let printed = parse_document(source).unwrap().print();
+        assert_eq!(serde_json::from_str::<Value>(&printed).unwrap(), value);
+    }
+
+    #[test]
+    fn string_escapes_are_not_comments() {
+        let source = r#"{"url": "http://host/* x */", "quote": "a \"// b\" \\", 'single': 'it\'s'}"#;
+        let stripped = strip_comments(source);
+        assert_eq!(stripped, source);
+        let printed = update_source(Some(source), &json5::from_str(source).unwrap(), KeyOrder::Keep);
+        assert!(printed.contains(r#""url": "http://host/* x */""#));
+        assert!(printed.contains(r#""quote": "a \"// b\" \\""#));
+        assert!(printed.contains(r#"'single': 'it\'s'"#));
+    }
+
+    #[test]
+    fn update_keeps_comments() {
+        let source = "// stack\n{\n    // the name\n    \"stack_name\": \"one\", // inline\n\n    \"root\": \".\"\n}\n";
+        let updated = update_source(Some(source), &json!({"stack_name": "two", "root": "."}), KeyOrder::Keep);
+        assert_eq!(
+            updated,
+            "// stack\n{\n    // the name\n    \"stack_name\": \"two\", // inline\n\n    \"root\": \".\"\n}\n"
+        );
+    }
+
+    #[test]
+    fn merge_order() {
+        let source = "{\n    \"b\": 1,\n    // about a\n    \"a\": 2\n}";
+        let value = json!({"a": 2, "b": 1, "c": 3});
+        let kept = update_source(Some(source), &value, KeyOrder::Keep);
+        assert_eq!(kept, "{\n    \"b\": 1,\n    // about a\n    \"a\": 2,\n    \"c\": 3\n}\n");
+        let canonical = update_source(Some(source), &value, KeyOrder::Canonical);
+        assert_eq!(canonical, "{\n    // about a\n    \"a\": 2,\n    \"b\": 1,\n    \"c\": 3\n}\n");
+    }
+
+    #[test]
+    fn merge_keeps_new_false_and_empty_values() {
+        let source = "{\n    \"project_name\": \"api\"\n}";
+        let value = json!({"project_name": "api", "enabled": false, "tags": [], "path": null});
+        let updated = update_source(Some(source), &value, KeyOrder::Keep);
+        assert_eq!(
+            updated,
+            "{\n    \"project_name\": \"api\",\n    \"enabled\": false,\n    \"tags\": []\n}\n"
+        );
+    }
+
+    #[test]
+    fn merge_matches_array_items_by_name() {
+        let source = concat!(
+            "[\n    // the api\n    {\"project_name\": \"api\"},\n",
+            "    // the web app\n    {\"project_name\": \"web\"}\n]"
+        );
+        let value = json!([{"project_name": "new"}, {"project_name": "web"}]);
+        let updated = update_source(Some(source), &value, KeyOrder::Keep);
+        let expected = concat!(
+            "[\n    {\n        \"project_name\": \"new\"\n    },\n",
+            "    // the web app\n    {\n        \"project_name\": \"web\"\n    }\n]\n"
+        );
+        assert_eq!(updated, expected);
+    }
+
+    #[test]
+    fn value_locations_skip_comments() {
+        let source = concat!(
+            "{\n  // comment with \"quotes\"\n  \"projects\": [\n",
+            "    /* first */ {\"project_name\": \"api\",},\n  ],\n}"
+        );
+        let locations = get_value_locations(source);
+        assert_eq!(locations.get("projects"), Some(&SourceLocation { line: 3, column: 15 }));
+        assert_eq!(locations.get("projects[0]"), Some(&SourceLocation { line: 4, column: 17 }));
+        assert_eq!(locations.get("projects[0].project_name"), Some(&SourceLocation { line: 4, column: 34 }));
+    }
+
+    #[test]
+    fn value_locations_before_a_syntax_error() {
+        let locations = get_value_locations("{\"a\": 1, \"b\": }");
+        assert_eq!(locations.get("a"), Some(&SourceLocation { line: 1, column: 7 }));
+        assert!(!locations.contains_key("c"));
+    }
+}
@@ -1,30 +1,38 @@
-use colored::Colorize;
-
 use crate::models::ldot_config_json::Configuration;
 use crate::models::stack_config_json::serialize_stack_config_to_file;
+use crate::utils::stage_graph_util::{self, ProjectSelector, StageReference};
 use crate::{
-    models::stack_config_json::{ProjectConfig, ScriptConfig, StackConfig, StageConfig},
+    models::stack_config_json::{CommandConfig, ProjectConfig, ScriptConfig, StackConfig, StageConfig},
     utils::generic_utils,
 };
-use std::process::Command;
-
-use super::configuration_util;
+use super::command_execution_util::{CommandContext, ExecutionReport, FailurePolicy};
+use super::diagnostics_util::Diagnostics;
+use super::interpolation_util::{self, VariableScope};
+use super::jsonc_util::KeyOrder;
+use super::{
+    command_execution_util, configuration_util, dotenv_util, environment_util, json_source_util, migration_util,
+    output_util, param_util, schema_util, stack_composition_util, stack_format_util, stack_path_util,
+};
+use super::stack_format_util::StackFormat;
 
+use serde_json::Value;
+use std::collections::BTreeMap;
 use std::fs;
 
-fn get_default_directory_from_console() -> String {
+fn get_default_directory_from_console(format: StackFormat) -> String {
     let default_dir = generic_utils::get_current_working_dir()
         .unwrap()
         .as_path()
         .display()
         .to_string()
-        + "/ldot_stack.json";
+        + "/"
+        + stack_format_util::get_default_file_name(format);
     println!("LDOT stack file name? Default: {}", default_dir);
     let mut dir = generic_utils::get_file_name_check_if_parent_dir_exists();
-    if dir == "" {
+    if dir.is_empty() {
         dir = default_dir;
     }
-    return dir.to_string();
+    dir.to_string()
 }
 
 fn get_stack_name_from_console() -> String {
@@ -37,60 +45,99 @@ fn get_stack_name_from_console() -> String {
     while stack_name.contains(" ") {
         stack_name = generic_utils::get_line_from_console_allow_blank();
     }
-    if stack_name == "" {
+    if stack_name.is_empty() {
         stack_name = default_stack_name;
     }
-    return stack_name.to_string();
+    stack_name.to_string()
 }
 
 fn get_stack_version_from_console() -> String {
     let default_stack_version = "1.0.0".to_string();
     println!("Stack version? Default: {}", default_stack_version);
     let mut stack_version = generic_utils::get_line_from_console_allow_blank();
-    if stack_version == "" {
+    if stack_version.is_empty() {
         stack_version = default_stack_version;
     }
-    return stack_version.to_string();
+    stack_version.to_string()
 }
 
 fn get_stack_description_from_console() -> String {
     let default_stack_description = "Stack Description".to_string();
     println!("Stack description? Default: {}", default_stack_description);
     let mut stack_description = generic_utils::get_line_from_console_allow_blank();
-    if stack_description == "" {
+    if stack_description.is_empty() {
         stack_description = default_stack_description;
     }
-    return stack_description.to_string();
+    stack_description.to_string()
 }
 
-pub fn generate_ldot_stack() -> Result<StackConfig, String> {
+pub fn generate_ldot_stack(format: StackFormat) -> Result<StackConfig, String> {
     //default directory
-    let dir = get_default_directory_from_console();
+    let dir = get_default_directory_from_console(format);
     let stack_name = get_stack_name_from_console();
     let stack_version = get_stack_version_from_console();
     let stack_description = get_stack_description_from_console();
     println!("Adding generic projects and scripts to your stack file, you can edit this directly");
     println!("Once you're done editing the file, load it with ldot load");
 
+    let schema = match schema_util::write_stack_schema(&dir) {
+        Ok(schema) => Some(schema),
+        Err(err) => {
+            eprintln!("Could not write the stack schema: {}", err);
+            None
+        }
+    };
+
     let stack_config: StackConfig = StackConfig {
+        schema,
+        schema_version: Some(migration_util::CURRENT_SCHEMA_VERSION),
+        min_ldot_version: None,
+        extends: None,
+        include: vec![],
         version: (stack_version),
         stack_name: (stack_name),
         description: (stack_description),
+        max_parallel: None,
+        root: None,
+        variables: BTreeMap::new(),
+        env: BTreeMap::new(),
+        env_files: vec![],
+        shell: None,
+        exec: None,
         projects: (vec![ProjectConfig {
             project_name: "some_project".to_string(),
             project_description: "some project description".to_string(),
+            path: None,
+            enabled: true,
+            tags: vec![],
+            env: BTreeMap::new(),
+            env_files: vec![],
+            group: false,
             stages: vec![StageConfig {
                 stage_name: "stage_name".to_string(),
+                aliases: vec![],
                 stage_description: "stage description".to_string(),
                 prerequisites: vec![],
-                commands: vec!["echo hello world".to_string()],
+                commands: vec![CommandConfig::Simple("echo hello world".to_string())],
+                shell: None,
+                exec: None,
+                continue_on_error: false,
+                env: BTreeMap::new(),
+                env_files: vec![],
             }],
         }]),
         scripts: (vec![ScriptConfig {
             script_name: ("script_name".to_string()),
             script_description: ("script description".to_string()),
-            commands: (vec!["echo hello world".to_string()]),
+            commands: (vec![CommandConfig::Simple("echo hello world".to_string())]),
+            shell: None,
+            exec: None,
+            env: BTreeMap::new(),
+            env_files: vec![],
+            params: vec![],
         }]),
+        groups: BTreeMap::new(),
+        profiles: vec![],
     };
 
     if let Err(err) = serialize_stack_config_to_file(&stack_config, &dir) {
@@ -100,69 +147,604 @@ pub fn generate_ldot_stack() -> Result<StackConfig, String> {
 }
 
 pub fn validate_ldot_stack(filename: String) -> Result<StackConfig, Box<dyn std::error::Error>> {
-    // Read the JSON content from the file
-    let json_str = std::fs::read_to_string(filename)?;
+    let (stack_config, diagnostics) = get_stack_diagnostics(&filename, None)?;
+    match stack_config {
+        Some(stack_config) if !diagnostics.has_errors() => Ok(stack_config),
+        _ => Err(diagnostics.get_error_summary().into()),
+    }
+}
+
+// Reads and checks a stack file, collecting every error and warning. The stack is returned when it could be parsed.
+pub fn get_stack_diagnostics(
+    filename: &str,
+    profile: Option<String>,
+) -> Result<(Option<StackConfig>, Diagnostics), Box<dyn std::error::Error>> {
+    // Read the stack file, its extension decides the format
+    let source = std::fs::read_to_string(filename)?;
+    let mut diagnostics = Diagnostics::new(filename, &source);
+    let format = stack_format_util::get_stack_format(filename);
+
+    // Syntax errors are reported by parse_stack_config below, with their position
+    let mut composed = false;
+    if let Ok(value) = stack_format_util::parse_stack_value(&source, format) {
+        composed = stack_composition_util::is_composed(filename, &value);
+        match migration_util::check_stack_version(&value) {
+            Ok(version) if version < migration_util::CURRENT_SCHEMA_VERSION => {
+                diagnostics.warning(
+                    "",
+                    format!(
+                        "Stack uses schema version {}, run \"ldot migrate {}\" to upgrade it to version {}",
+                        version,
+                        filename,
+                        migration_util::CURRENT_SCHEMA_VERSION
+                    ),
+                );
+                diagnostics.forget_locations();
+            }
+            Ok(_) => {}
+            Err(e) => {
+                diagnostics.error("", e);
+                return Ok((None, diagnostics));
+            }
+        }
+    }
+
+    if composed {
+        return Ok(get_composed_stack_diagnostics(filename, profile, diagnostics));
+    }
+    let mut stack_config: StackConfig =
+        match stack_format_util::parse_stack_config(&source, format) {
+            Ok(stack_config) => stack_config,
+            Err(e) => {
+                diagnostics.error_at(filename, e.line.unwrap_or(0), e.column.unwrap_or(0), e.message);
+                return Ok((None, diagnostics));
+            }
+        };
+
+    check_profile(&mut stack_config, profile, &mut diagnostics);
+    check_stack(&stack_config, filename, &mut diagnostics);
+    Ok((Some(stack_config), diagnostics))
+}
+
+// Stacks that extend or include other files are checked once merged, each problem is reported in its own file
+fn get_composed_stack_diagnostics(
+    filename: &str,
+    profile: Option<String>,
+    mut diagnostics: Diagnostics,
+) -> (Option<StackConfig>, Diagnostics) {
+    let composed = match stack_composition_util::compose_stack(filename) {
+        Ok(composed) => composed,
+        Err(e) => {
+            if let Ok(source) = std::fs::read_to_string(&e.file) {
+                diagnostics.add_source(&e.file, &source);
+            }
+            let (line, column) = (e.error.line.unwrap_or(0), e.error.column.unwrap_or(0));
+            diagnostics.error_at(&e.file, line, column, e.error.message);
+            return (None, diagnostics);
+        }
+    };
+    for (file, source) in &composed.sources {
+        diagnostics.add_source(file, source);
+    }
+    for origin in &composed.origins {
+        diagnostics.add_origin(&origin.path, &origin.file, &origin.source_path);
+    }
+
+    let mut stack_config: StackConfig = match serde_json::from_value(composed.value) {
+        Ok(stack_config) => stack_config,
+        Err(e) => {
+            diagnostics.error("", e.to_string());
+            return (None, diagnostics);
+        }
+    };
+    check_profile(&mut stack_config, profile, &mut diagnostics);
+    check_stack(&stack_config, filename, &mut diagnostics);
+    (Some(stack_config), diagnostics)
+}
 
-    // Deserialize the JSON into a StackConfig
-    let stack_config: StackConfig = serde_json::from_str(&json_str)?;
+// Checks the stack with the given profile, or the one stored for it, applied
+fn check_profile(stack_config: &mut StackConfig, profile: Option<String>, diagnostics: &mut Diagnostics) {
+    if let Err(e) = apply_selected_profile(stack_config, profile) {
+        diagnostics.error("profiles", e);
+    }
+}
 
-    // Perform validation checks here
+fn check_name(name: &str, kind: &str, path: &str, diagnostics: &mut Diagnostics) {
+    if name.is_empty() {
+        diagnostics.error(path, format!("{} name is empty", kind));
+    } else if name.contains(' ') {
+        diagnostics.error(path, format!("{} name contains a space: {}", kind, name));
+    }
+}
+
+fn check_stack(stack_config: &StackConfig, filename: &str, diagnostics: &mut Diagnostics) {
     let mut unique_project_names: Vec<String> = Vec::new();
     let mut unique_script_names: Vec<String> = Vec::new();
-    if stack_config.stack_name.is_empty() {
-        return Err("Stack name is empty".into());
+    check_name(&stack_config.stack_name, "Stack", "stack_name", diagnostics);
+
+    for (project_index, project) in stack_config.projects.iter().enumerate() {
+        let project_path = format!("projects[{}]", project_index);
+        let name_path = format!("{}.project_name", project_path);
+        check_name(&project.project_name, "Project", &name_path, diagnostics);
+        if unique_project_names.contains(&project.project_name) {
+            diagnostics.error(&name_path, format!("Project name not unique: {}", project.project_name));
+        }
+        unique_project_names.push(project.project_name.to_string());
+        for (tag_index, tag) in project.tags.iter().enumerate() {
+            check_name(tag, "Tag", &format!("{}.tags[{}]", project_path, tag_index), diagnostics);
+        }
+
+        let mut unique_stage_names: Vec<String> = Vec::new();
+        for (stage_index, stage) in project.stages.iter().enumerate() {
+            let name_path = format!("{}.stages[{}].stage_name", project_path, stage_index);
+            check_name(&stage.stage_name, "Stage", &name_path, diagnostics);
+            if unique_stage_names.contains(&stage.stage_name) {
+                diagnostics.error(
+                    &name_path,
+                    format!("Stage name not unique: {}:{}", project.project_name, stage.stage_name),
+                );
+            }
+            unique_stage_names.push(stage.stage_name.to_string());
+        }
+
+        // Aliases share the names of the project's stages, so they must not repeat a stage name or another alias
+        let mut unique_aliases: Vec<String> = Vec::new();
+        for (stage_index, stage) in project.stages.iter().enumerate() {
+            for (alias_index, alias) in stage.aliases.iter().enumerate() {
+                let alias_path = format!("{}.stages[{}].aliases[{}]", project_path, stage_index, alias_index);
+                check_name(alias, "Stage alias", &alias_path, diagnostics);
+                if unique_stage_names.contains(alias) {
+                    diagnostics.error(
+                        &alias_path,
+                        format!("Stage alias is also a stage name: {}:{}", project.project_name, alias),
+                    );
+                } else if unique_aliases.contains(alias) {
+                    diagnostics.error(
+                        &alias_path,
+                        format!("Stage alias not unique: {}:{}", project.project_name, alias),
+                    );
+                }
+                unique_aliases.push(alias.to_string());
+            }
+        }
     }
-    if stack_config.stack_name.contains(" ") {
-        return Err("Stack name contains a space".into());
+
+    for (script_index, script) in stack_config.scripts.iter().enumerate() {
+        let name_path = format!("scripts[{}].script_name", script_index);
+        check_name(&script.script_name, "Script", &name_path, diagnostics);
+        if unique_script_names.contains(&script.script_name) {
+            diagnostics.error(&name_path, format!("Script name not unique: {}", script.script_name));
+        }
+        unique_script_names.push(script.script_name.to_string());
     }
 
-    for project in &stack_config.projects {
-        if project.project_name.is_empty() {
-            return Err("Project name is empty".into());
+    for (group_name, members) in &stack_config.groups {
+        let group_path = format!("groups.{}", group_name);
+        check_name(group_name, "Group", &group_path, diagnostics);
+        if unique_project_names.contains(group_name) {
+            diagnostics.error(&group_path, format!("Group name is also a project name: {}", group_name));
+        }
+        for (member_index, member) in members.iter().enumerate() {
+            let matches = stack_config
+                .projects
+                .iter()
+                .any(|project| generic_utils::matches_glob(member, &project.project_name));
+            if !matches {
+                diagnostics.error(
+                    &format!("{}[{}]", group_path, member_index),
+                    format!("Group member matches no project in {}: {}", group_name, member),
+                );
+            }
         }
-        if project.project_name.contains(" ") {
-            return Err(format!("Project name contains a space: {}", project.project_name).into());
+    }
+
+    let mut unique_profile_names: Vec<String> = Vec::new();
+    for (profile_index, profile) in stack_config.profiles.iter().enumerate() {
+        let profile_path = format!("profiles[{}]", profile_index);
+        let name_path = format!("{}.profile_name", profile_path);
+        check_name(&profile.profile_name, "Profile", &name_path, diagnostics);
+        if unique_profile_names.contains(&profile.profile_name) {
+            diagnostics.error(&name_path, format!("Profile name not unique: {}", profile.profile_name));
         }
-        if unique_project_names.iter().any(|unique_project_name| {
-            project.project_name.to_string() == unique_project_name.to_string()
-        }) {
-            return Err(format!("Project name not unique: {}", project.project_name).into());
+        unique_profile_names.push(profile.profile_name.to_string());
+        for (project_index, project_name) in profile.projects.iter().flatten().enumerate() {
+            if stage_graph_util::find_project(stack_config, project_name).is_none() {
+                diagnostics.error(
+                    &format!("{}.projects[{}]", profile_path, project_index),
+                    format!("Project not found in profile {}: {}", profile.profile_name, project_name),
+                );
+            }
+        }
+    }
+
+    check_variables(stack_config, filename, diagnostics);
+    // Prerequisites may name the stages of groups
+    let mut expanded_stack_config = stack_config.clone();
+    stage_graph_util::expand_groups(&mut expanded_stack_config);
+    check_prerequisites(&expanded_stack_config, diagnostics);
+
+    let stack_scope = interpolation_util::get_stack_scope(stack_config, filename);
+    check_env(&stack_config.env, &stack_config.env_files, &stack_scope, "", diagnostics);
+    for (project_index, project) in stack_config.projects.iter().enumerate() {
+        let project_path = format!("projects[{}]", project_index);
+        let project_scope = interpolation_util::get_stage_scope(stack_config, filename, project, "");
+        check_env(&project.env, &project.env_files, &project_scope, &project_path, diagnostics);
+        for (stage_index, stage) in project.stages.iter().enumerate() {
+            let stage_path = format!("{}.stages[{}]", project_path, stage_index);
+            let stage_scope = interpolation_util::get_stage_scope(stack_config, filename, project, &stage.stage_name);
+            check_env(&stage.env, &stage.env_files, &stage_scope, &stage_path, diagnostics);
+            check_commands(&stage.commands, &stage_scope, &stage_path, diagnostics);
+            // Stages with prerequisites but no commands only group other stages
+            if stage.commands.is_empty() && stage.prerequisites.is_empty() {
+                diagnostics.warning(
+                    &format!("{}.commands", stage_path),
+                    format!("Stage has no commands or prerequisites: {}:{}", project.project_name, stage.stage_name),
+                );
+            }
+        }
+    }
+    for (script_index, script) in stack_config.scripts.iter().enumerate() {
+        let script_path = format!("scripts[{}]", script_index);
+        let script_scope = interpolation_util::get_script_scope(stack_config, filename, script);
+        check_env(&script.env, &script.env_files, &script_scope, &script_path, diagnostics);
+        check_commands(&script.commands, &script_scope, &script_path, diagnostics);
+        check_params(script, &script_path, diagnostics);
+        if script.commands.is_empty() {
+            diagnostics.warning(
+                &format!("{}.commands", script_path),
+                format!("Script has no commands: {}", script.script_name),
+            );
+        }
+    }
+}
+
+// Parameters are used as "${params.NAME}" in the script's commands, env or env_files
+fn check_params(script: &ScriptConfig, script_path: &str, diagnostics: &mut Diagnostics) {
+    let references: Vec<String> = script
+        .commands
+        .iter()
+        .flat_map(interpolation_util::get_command_templates)
+        .chain(script.env.values().cloned())
+        .chain(script.env_files.iter().cloned())
+        .filter_map(|template| interpolation_util::get_references(&template).ok())
+        .flatten()
+        .collect();
+    let mut unique_param_names: Vec<String> = Vec::new();
+    for (param_index, param) in script.params.iter().enumerate() {
+        let param_path = format!("{}.params[{}]", script_path, param_index);
+        let name_path = format!("{}.param_name", param_path);
+        check_name(&param.param_name, "Parameter", &name_path, diagnostics);
+        if param.param_name.contains('=') || param.param_name.contains('.') {
+            diagnostics.error(&name_path, format!("Parameter name contains \"=\" or a dot: {}", param.param_name));
+        }
+        if unique_param_names.contains(&param.param_name) {
+            diagnostics.error(
+                &name_path,
+                format!("Parameter name not unique in {}: {}", script.script_name, param.param_name),
+            );
+        }
+        unique_param_names.push(param.param_name.to_string());
+        if let Err(e) = param_util::get_default_value(param) {
+            diagnostics.error(&format!("{}.default", param_path), e);
+        }
+        if !references.contains(&format!("params.{}", param.param_name)) {
+            diagnostics.warning(
+                &name_path,
+                format!("Parameter is never used in {}: {}", script.script_name, param.param_name),
+            );
         }
-        unique_project_names.push(project.project_name.to_string());
     }
+}
 
+fn check_variables(stack_config: &StackConfig, filename: &str, diagnostics: &mut Diagnostics) {
+    let stack_scope = interpolation_util::get_stack_scope(stack_config, filename);
+    for (name, value) in &stack_config.variables {
+        let path = format!("variables.{}", name);
+        if name.is_empty() || name.contains('.') || name.contains(' ') {
+            diagnostics.error(&path, format!("Variable name must not be empty or contain a dot or a space: {}", name));
+        }
+        if let Err(e) = interpolation_util::check_template(value, &stack_scope) {
+            diagnostics.error(&path, e);
+        }
+    }
+
+    // Every template of the stack, to find variables that nothing refers to
+    let mut templates: Vec<String> = vec![];
+    templates.extend(stack_config.variables.values().cloned());
+    templates.extend(stack_config.env.values().cloned());
+    templates.extend(stack_config.env_files.iter().cloned());
+    for project in &stack_config.projects {
+        templates.extend(project.env.values().cloned());
+        templates.extend(project.env_files.iter().cloned());
+        for stage in &project.stages {
+            templates.extend(stage.env.values().cloned());
+            templates.extend(stage.env_files.iter().cloned());
+            templates.extend(stage.commands.iter().flat_map(interpolation_util::get_command_templates));
+        }
+    }
     for script in &stack_config.scripts {
-        if script.script_name.is_empty() {
-            return Err("Script name is empty".into());
+        templates.extend(script.env.values().cloned());
+        templates.extend(script.env_files.iter().cloned());
+        templates.extend(script.commands.iter().flat_map(interpolation_util::get_command_templates));
+    }
+    let references: Vec<String> = templates
+        .iter()
+        .filter_map(|template| interpolation_util::get_references(template).ok())
+        .flatten()
+        .collect();
+    for name in stack_config.variables.keys() {
+        if !references.contains(name) {
+            diagnostics.warning(&format!("variables.{}", name), format!("Variable is never used: {}", name));
         }
-        if script.script_name.contains(" ") {
-            return Err(format!("Script name contains a space: {}", script.script_name).into());
+    }
+}
+
+fn check_prerequisites(stack_config: &StackConfig, diagnostics: &mut Diagnostics) {
+    for (project_index, project) in stack_config.projects.iter().enumerate() {
+        for (stage_index, stage) in project.stages.iter().enumerate() {
+            for (prerequisite_index, prerequisite) in stage.prerequisites.iter().enumerate() {
+                let path = format!(
+                    "projects[{}].stages[{}].prerequisites[{}]",
+                    project_index, stage_index, prerequisite_index
+                );
+                match stage_graph_util::parse_stage_reference(prerequisite, &project.project_name) {
+                    Ok(reference) => {
+                        if stage_graph_util::find_stage(stack_config, &reference).is_none() {
+                            diagnostics.error(
+                                &path,
+                                format!(
+                                    "Prerequisite not found: {} (required by {}:{})",
+                                    reference, project.project_name, stage.stage_name
+                                ),
+                            );
+                        }
+                    }
+                    Err(e) => diagnostics.error(&path, e),
+                }
+            }
+        }
+    }
+
+    // Cycles are reported at the prerequisite that leads from the first stage of the cycle to the next one
+    for cycle in stage_graph_util::find_prerequisite_cycles(stack_config) {
+        let description: Vec<String> = cycle.iter().map(|reference| reference.to_string()).collect();
+        let message = format!("Prerequisite cycle detected: {}", description.join(" -> "));
+        diagnostics.error(&get_prerequisite_path(stack_config, &cycle[0], &cycle[1]), message);
+    }
+}
+
+fn get_prerequisite_path(stack_config: &StackConfig, from: &StageReference, to: &StageReference) -> String {
+    for (project_index, project) in stack_config.projects.iter().enumerate() {
+        if project.project_name != from.project {
+            continue;
         }
-        if unique_script_names.iter().any(|unique_script_name| {
-            script.script_name.to_string() == unique_script_name.to_string()
-        }) {
-            return Err(format!("Script name not unique: {}", script.script_name).into());
+        for (stage_index, stage) in project.stages.iter().enumerate() {
+            if stage.stage_name != from.stage {
+                continue;
+            }
+            let stage_path = format!("projects[{}].stages[{}]", project_index, stage_index);
+            for (prerequisite_index, prerequisite) in stage.prerequisites.iter().enumerate() {
+                let reference = stage_graph_util::parse_stage_reference(prerequisite, &project.project_name)
+                    .ok()
+                    .and_then(|reference| stage_graph_util::resolve_stage_reference(stack_config, &reference));
+                if reference.as_ref() == Some(to) {
+                    return format!("{}.prerequisites[{}]", stage_path, prerequisite_index);
+                }
+            }
+            return stage_path;
         }
-        unique_script_names.push(script.script_name.to_string());
     }
+    String::new()
+}
 
-    // You can add more validation logic as needed
+fn check_env(
+    env: &BTreeMap<String, String>,
+    env_files: &[String],
+    scope: &VariableScope,
+    path: &str,
+    diagnostics: &mut Diagnostics,
+) {
+    for (key, value) in env {
+        let value_path = json_source_util::join_path(&json_source_util::join_path(path, "env"), key);
+        if key.is_empty() || key.contains('=') {
+            diagnostics.error(&value_path, format!("Invalid environment variable name: \"{}\"", key));
+        }
+        if let Err(e) = interpolation_util::check_template(value, scope) {
+            diagnostics.error(&value_path, e);
+        }
+    }
+    for (index, env_file) in env_files.iter().enumerate() {
+        if let Err(e) = interpolation_util::check_template(env_file, scope) {
+            diagnostics.error(&format!("{}[{}]", json_source_util::join_path(path, "env_files"), index), e);
+        }
+    }
+}
 
-    Ok(stack_config)
+fn check_commands(commands: &[CommandConfig], scope: &VariableScope, path: &str, diagnostics: &mut Diagnostics) {
+    for (index, command_config) in commands.iter().enumerate() {
+        let command_path = format!("{}.commands[{}]", path, index);
+        for template in interpolation_util::get_command_templates(command_config) {
+            if let Err(e) = interpolation_util::check_template(&template, scope) {
+                diagnostics.error(&command_path, e);
+            }
+        }
+        let command = command_config.to_details();
+        if command.run.trim().is_empty() {
+            diagnostics.error(&command_path, "Command is empty");
+        }
+        if let Some(timeout) = &command.timeout {
+            if let Err(e) = generic_utils::parse_duration(timeout) {
+                diagnostics.error(&format!("{}.timeout", command_path), e);
+            }
+        }
+    }
+}
+
+// The stack file as written, or with what it extends, includes, its local overrides and the active profile
+// merged in. Disabled projects are still shown. Printed in the format of the stack file.
+pub fn show_stack(stack_name: String, resolved: bool, profile: Option<String>) -> Result<String, String> {
+    let stack_file = configuration_util::get_ldot_stack_from_stack_name(&stack_name)?;
+    if !resolved {
+        return fs::read_to_string(&stack_file).map_err(|e| format!("Could not open and read stack file: {} {}", stack_file, e));
+    }
+    let mut stack_config = stack_composition_util::read_stack_config(&stack_file)?;
+    apply_selected_profile(&mut stack_config, profile)?;
+    let value = serde_json::to_value(&stack_config).map_err(|e| e.to_string())?;
+    stack_format_util::serialize_stack_value(value, stack_format_util::get_stack_format(&stack_file), None, KeyOrder::Canonical)
+}
+
+// Appends an empty project and writes the stack file back, JSON files keep their comments and key order
+pub fn add_project(
+    filename: &str,
+    project_name: String,
+    description: Option<String>,
+    path: Option<String>,
+    drop_comments: bool,
+) -> Result<(), String> {
+    // Only the file itself is rewritten and it is read as it is written, so a file that extends or includes
+    // others may leave out what it gets from them. Their projects count towards unique names.
+    let source = match std::fs::read_to_string(filename) {
+        Ok(source) => source,
+        Err(e) => return Err(format!("Could not open and read stack file: {} {}", filename, e)),
+    };
+    let format = stack_format_util::get_stack_format(filename);
+    let mut value = stack_format_util::parse_partial_stack_value(&source, format)
+        .map_err(|e| format!("Could not parse stack file: {} {}", filename, e.message))?;
+    let composed_stack_config = stack_composition_util::read_stack_config(filename)?;
+    if project_name.is_empty() || project_name.contains(' ') {
+        return Err(format!("Project name must not be empty or contain a space: {}", project_name));
+    }
+    if stage_graph_util::find_project(&composed_stack_config, &project_name).is_some() {
+        return Err(format!("Project name not unique: {}", project_name));
+    }
+    let project = serde_json::to_value(ProjectConfig {
+        project_name,
+        project_description: description.unwrap_or_default(),
+        path,
+        enabled: true,
+        tags: vec![],
+        env: BTreeMap::new(),
+        env_files: vec![],
+        stages: vec![],
+        group: false,
+    })
+    .map_err(|e| e.to_string())?;
+    let projects = match value.as_object_mut() {
+        Some(values) => values.entry("projects").or_insert_with(|| Value::Array(vec![])),
+        None => return Err(format!("Expected the stack file to contain an object: {}", filename)),
+    };
+    match projects {
+        Value::Array(projects) => projects.push(project),
+        _ => return Err(format!("projects must be a list: {}", filename)),
+    }
+    let content = stack_format_util::serialize_stack_value(value, format, Some(&source), KeyOrder::Keep)?;
+    check_lost_comments(filename, &source, &content, drop_comments, true)?;
+    fs::write(filename, content).map_err(|e| format!("Could not write stack file: {} {}", filename, e))
+}
+
+// YAML and TOML files lose their comments when they are rewritten, so that needs drop_comments. Without writing
+// (a check or a dry run) the loss is only reported.
+fn check_lost_comments(
+    filename: &str,
+    source: &str,
+    rewritten: &str,
+    drop_comments: bool,
+    write: bool,
+) -> Result<(), String> {
+    let format = stack_format_util::get_stack_format(filename);
+    let (line, comment) = match stack_format_util::find_lost_comment(source, rewritten, format) {
+        Some(lost) => lost,
+        None => return Ok(()),
+    };
+    if !write {
+        eprintln!("Rewriting {} would remove its comments, e.g. line {}: {}", filename, line, comment);
+        Ok(())
+    } else if drop_comments {
+        eprintln!("Removed the comments of {}, e.g. line {}: {}", filename, line, comment);
+        Ok(())
+    } else {
+        Err(format!(
+            "Rewriting {} would remove its comments, e.g. line {}: {}. YAML and TOML comments can't be kept, \
+             pass --drop-comments to rewrite it anyway",
+            filename, line, comment
+        ))
+    }
+}
+
+// Formats a stack file and returns whether it changed. With check, the file is left untouched.
+pub fn format_stack_file(filename: &str, check: bool, omit_defaults: bool, drop_comments: bool) -> Result<bool, String> {
+    let source = match std::fs::read_to_string(filename) {
+        Ok(source) => source,
+        Err(e) => return Err(format!("Could not open and read stack file: {} {}", filename, e)),
+    };
+    let format = stack_format_util::get_stack_format(filename);
+    let partial = stack_format_util::parse_stack_value(&source, format)
+        .is_ok_and(|value| stack_composition_util::is_partial(filename, &value));
+    let formatted = stack_format_util::format_stack_source(&source, format, omit_defaults, partial)
+        .map_err(|e| match (e.line, e.column) {
+            (Some(line), Some(column)) => format!("Could not parse stack file: {}:{}:{} {}", filename, line, column, e.message),
+            _ => format!("Could not parse stack file: {} {}", filename, e.message),
+        })?;
+    if formatted == source {
+        return Ok(false);
+    }
+    check_lost_comments(filename, &source, &formatted, drop_comments, !check)?;
+    if !check {
+        fs::write(filename, formatted).map_err(|e| format!("Could not write stack file: {} {}", filename, e))?;
+    }
+    Ok(true)
+}
+
+// Converts a legacy camelCase stack or configuration file to the current shape. With dry_run, the
+// changes are printed as a diff and the file is left untouched.
+pub fn migrate_file(filename: &str, dry_run: bool, drop_comments: bool) -> Result<(), String> {
+    let source = match std::fs::read_to_string(filename) {
+        Ok(source) => source,
+        Err(e) => return Err(format!("Could not open and read file: {} {}", filename, e)),
+    };
+    let format = stack_format_util::get_stack_format(filename);
+    let value = stack_format_util::parse_stack_value(&source, format)
+        .map_err(|e| format!("Could not parse file: {} {}", filename, e.message))?;
+
+    let migration = if migration_util::is_legacy_config(&value) {
+        let migration = migration_util::migrate_config(&value)?;
+        serde_json::from_value::<Configuration>(migration.value.clone())
+            .map_err(|e| format!("The migrated configuration is not valid: {}", e))?;
+        migration
+    } else if migration_util::check_stack_version(&value)? < migration_util::CURRENT_SCHEMA_VERSION {
+        let migration = migration_util::upgrade_stack(&value)?;
+        serde_json::from_value::<StackConfig>(migration.value.clone())
+            .map_err(|e| format!("The migrated stack is not valid: {}", e))?;
+        migration
+    } else {
+        println!("Already up to date: {}", filename);
+        return Ok(());
+    };
+
+    let migrated = stack_format_util::serialize_stack_value(migration.value, format, Some(&source), KeyOrder::Canonical)?;
+    check_lost_comments(filename, &source, &migrated, drop_comments, !dry_run)?;
+    for note in &migration.notes {
+        println!("Note: {}", note);
+    }
+    if dry_run {
+        print!("{}", generic_utils::get_unified_diff(&source, &migrated, filename));
+        println!("Dry run, {} was not changed.", filename);
+    } else {
+        fs::write(filename, migrated).map_err(|e| format!("Could not write file: {} {}", filename, e))?;
+        println!("Migrated: {}", filename);
+    }
+    Ok(())
 }
 
 pub fn load_ldot_stack(filename: String) {
     // Validation logic
-    let filename_absolute: String;
-    match fs::canonicalize(filename.to_string()) {
-        Ok(file) => {
-            filename_absolute = file.as_path().display().to_string();
-        }
+    let filename_absolute: String = match fs::canonicalize(&filename) {
+        Ok(file) => file.as_path().display().to_string(),
         Err(err) => {
             eprintln!("Could not convert filename to canonical name: {}", err);
             std::process::exit(1);
         }
-    }
+    };
 
     match validate_ldot_stack(filename_absolute.to_string()) {
         Ok(_) => {
@@ -187,12 +769,11 @@ pub fn load_ldot_stack(filename: String) {
 
     if ldot_configuration
         .registered_stack_files
-        .iter()
-        .any(|stack_file| filename_absolute.to_string() == stack_file.to_string())
+        .contains(&filename_absolute)
     {
         eprintln!(
             "Stack file name not unique: {}",
-            filename_absolute.to_string()
+            filename_absolute
         );
         std::process::exit(1);
     }
@@ -214,16 +795,13 @@ pub fn load_ldot_stack(filename: String) {
 
 pub fn unload_ldot_stack(filename: String) {
     // Validation logic
-    let filename_absolute: String;
-    match fs::canonicalize(filename.to_string()) {
-        Ok(file) => {
-            filename_absolute = file.as_path().display().to_string();
-        }
+    let filename_absolute: String = match fs::canonicalize(&filename) {
+        Ok(file) => file.as_path().display().to_string(),
         Err(err) => {
             eprintln!("Could not convert filename to canonical name: {}", err);
             std::process::exit(1);
         }
-    }
+    };
 
     let mut ldot_configuration: Configuration;
     match configuration_util::get_configuration() {
@@ -239,17 +817,16 @@ pub fn unload_ldot_stack(filename: String) {
 
     if ldot_configuration
         .registered_stack_files
-        .iter()
-        .any(|stack_file| filename_absolute.to_string() == stack_file.to_string())
+        .contains(&filename_absolute)
     {
         ldot_configuration
             .registered_stack_files
             .retain(|x| x != &filename_absolute.to_string());
-        eprintln!("Stack file unloaded: {}", filename_absolute.to_string())
+        eprintln!("Stack file unloaded: {}", filename_absolute)
     } else {
         eprintln!(
             "Could not find stack file in configuration: {}",
-            filename_absolute.to_string()
+            filename_absolute
         );
         std::process::exit(1);
     }
@@ -265,149 +842,348 @@ pub fn unload_ldot_stack(filename: String) {
     }
 }
 
-pub fn get_ldot_stack_config(ldot_stack_file: String) -> StackConfig {
-    // Read the JSON content from the file
-    let json_str: String;
-    match std::fs::read_to_string(&ldot_stack_file) {
-        Ok(file_string) => {
-            json_str = file_string;
-        }
-        Err(e) => {
-            eprintln!(
-                "Could not open and read stack file: {} {}",
-                ldot_stack_file, e
-            );
-            std::process::exit(2);
+// Applies a profile's variables and env over the stack's. A profile that lists projects disables every other project.
+pub fn apply_profile(stack_config: &mut StackConfig, profile_name: &str) -> Result<(), String> {
+    let profile = match stack_config
+        .profiles
+        .iter()
+        .find(|profile| profile.profile_name == profile_name)
+    {
+        Some(profile) => profile.clone(),
+        None => return Err(format!("Profile not found: {}", profile_name)),
+    };
+    stack_config.variables.extend(profile.variables);
+    stack_config.env.extend(profile.env);
+    if let Some(projects) = &profile.projects {
+        for project in &mut stack_config.projects {
+            project.enabled = project.enabled && projects.contains(&project.project_name);
         }
     }
+    Ok(())
+}
 
-    let stack_config: StackConfig;
-    match serde_json::from_str(&json_str) {
-        Ok(json_str_parsed) => {
-            stack_config = json_str_parsed;
-        }
+// Applies the profile given with --profile, or the one stored for the stack with "ldot config profile". A stored
+// profile that was since removed from the stack file is reported as such, with how to change it.
+fn apply_selected_profile(stack_config: &mut StackConfig, profile: Option<String>) -> Result<(), String> {
+    if let Some(profile) = profile {
+        return apply_profile(stack_config, &profile);
+    }
+    let stack_name = stack_config.stack_name.to_string();
+    match configuration_util::get_active_profile(&stack_name) {
+        Some(profile) => apply_profile(stack_config, &profile).map_err(|_| {
+            format!(
+                "Profile stored for stack {} no longer exists: {}. Change it with \"ldot --stack {} config profile <profile>\" or clear it with --clear",
+                stack_name, profile, stack_name
+            )
+        }),
+        None => Ok(()),
+    }
+}
+
+// Registered stacks are found by their name alone. Commands that run a stack check it first, so a stack with errors
+// or a stale stored profile is reported as such instead of as an unknown stack.
+pub fn check_registered_stack(stack_file: &str, profile: Option<String>) -> Result<(), String> {
+    if let Ok(mut stack_config) = stack_composition_util::read_stack_config(stack_file) {
+        apply_selected_profile(&mut stack_config, profile.clone())?;
+    }
+    let (_, diagnostics) = get_stack_diagnostics(stack_file, profile)
+        .map_err(|e| format!("Could not open and read stack file: {} {}", stack_file, e))?;
+    if diagnostics.has_errors() {
+        return Err(format!(
+            "Stack file has errors, run \"ldot validate {}\" for details: {}",
+            stack_file,
+            diagnostics.get_error_summary()
+        ));
+    }
+    Ok(())
+}
+
+// Reads a registered stack with its local overrides and the given profile, or the one stored for the stack,
+// leaving out disabled projects
+pub fn get_ldot_stack_config(ldot_stack_file: String, profile: Option<String>) -> StackConfig {
+    match read_ldot_stack_config(&ldot_stack_file, profile) {
+        Ok(stack_config) => stack_config,
         Err(e) => {
-            eprintln!(
-                "Could not parse stack file to JSON: {} {}",
-                ldot_stack_file, e
-            );
-            std::process::exit(2);
+            eprintln!("{}", e);
+            std::process::exit(command_execution_util::EXIT_STACK_FILE_ERROR);
         }
     }
-    return stack_config;
+}
+
+// The stack as it is executed: composed, with its profile applied, without disabled projects and with a project
+// for every group
+pub fn read_ldot_stack_config(ldot_stack_file: &str, profile: Option<String>) -> Result<StackConfig, String> {
+    let mut stack_config = stack_composition_util::read_stack_config(ldot_stack_file)?;
+    apply_selected_profile(&mut stack_config, profile)?;
+    stage_graph_util::remove_disabled_projects(&mut stack_config);
+    stage_graph_util::expand_groups(&mut stack_config);
+    Ok(stack_config)
 }
 
 pub fn execute_stack_stage_script(
     stack: String,
-    project: String,
-    stage: String,
-) -> Result<Vec<std::string::String>, Vec<std::string::String>> {
-    let ldot_stack_file_name: String;
-    match configuration_util::get_ldot_stack_from_stack_name(&stack) {
-        Ok(filename) => {
-            ldot_stack_file_name = filename;
-        }
+    selector: ProjectSelector,
+    stages: Vec<String>,
+    args: Vec<String>,
+    jobs: Option<usize>,
+    keep_going: bool,
+    profile: Option<String>,
+) -> ExecutionReport {
+    let ldot_stack_file_name: String = match configuration_util::get_ldot_stack_from_stack_name(&stack) {
+        Ok(filename) => filename,
         Err(e) => {
             eprintln!("{}", e);
-            std::process::exit(1);
+            std::process::exit(command_execution_util::EXIT_FAILURE);
         }
-    }
+    };
 
-    let ldot_stack_config = get_ldot_stack_config(ldot_stack_file_name);
+    output_util::print_verbose(format!("Stack file: {}", ldot_stack_file_name));
+    if let Err(e) = check_registered_stack(&ldot_stack_file_name, profile.clone()) {
+        eprintln!("{}", e);
+        std::process::exit(command_execution_util::EXIT_FAILURE);
+    }
+    let ldot_stack_config = get_ldot_stack_config(ldot_stack_file_name.to_string(), profile);
 
-    for stack_project in ldot_stack_config.projects {
-        if stack_project.project_name == project {
-            for project_stage in stack_project.stages {
-                if project_stage.stage_name == stage {
-                    println!("Executing {} commands", project_stage.commands.len());
-                    return execute_command_from_list(project_stage.commands);
+    // A single project must have every stage, selected projects without a stage are skipped for it
+    let mut targets: Vec<StageReference> = vec![];
+    for stage in stages {
+        match &selector {
+            ProjectSelector::Name(project) if selector.is_single_project() => targets.push(StageReference {
+                project: project.to_string(),
+                stage,
+            }),
+            _ => {
+                let (stage_targets, skipped) = stage_graph_util::select_stage_targets(&ldot_stack_config, &selector, &stage);
+                for project in skipped {
+                    output_util::print_info(format!("Skipping {}: no stage named {}", project, stage));
+                }
+                if stage_targets.is_empty() {
+                    return ExecutionReport::error(
+                        format!("No selected project has a stage named {} ({})", stage, selector),
+                        command_execution_util::EXIT_FAILURE,
+                    );
                 }
+                targets.extend(stage_targets);
             }
         }
     }
+    let execution_order = match stage_graph_util::resolve_execution_order(&ldot_stack_config, &targets) {
+        Ok(order) => order,
+        Err(e) => return ExecutionReport::error(e, command_execution_util::EXIT_FAILURE),
+    };
+    let order: Vec<String> = execution_order.iter().map(|reference| reference.to_string()).collect();
+    output_util::print_verbose(format!("Execution order: {}", order.join(", ")));
+    // Stages named by an alias get the forwarded arguments too
+    let targets: Vec<StageReference> = targets
+        .iter()
+        .filter_map(|target| stage_graph_util::resolve_stage_reference(&ldot_stack_config, target))
+        .collect();
 
-    // If no matching script is found, you should return an error here.
-    Err(vec!["Stage not found".to_string()])
+    let jobs = jobs.or(ldot_stack_config.max_parallel).unwrap_or(1);
+    command_execution_util::execute_stage_graph(
+        &ldot_stack_config,
+        &ldot_stack_file_name,
+        &execution_order,
+        &targets,
+        &args,
+        jobs,
+        keep_going,
+    )
+}
+
+// The resolved environment of a project, or of one of its stages, as KEY=value lines sorted by name
+pub fn get_stack_environment(
+    stack: String,
+    project: String,
+    stage: Option<String>,
+    profile: Option<String>,
+) -> Result<Vec<String>, String> {
+    let ldot_stack_file_name: String = configuration_util::get_ldot_stack_from_stack_name(&stack)?;
+    check_registered_stack(&ldot_stack_file_name, profile.clone())?;
+    let ldot_stack_config = get_ldot_stack_config(ldot_stack_file_name.to_string(), profile);
+
+    let project_config = match stage_graph_util::find_project(&ldot_stack_config, &project) {
+        Some(project_config) => project_config,
+        None => return Err(format!("Project not found: {}", project)),
+    };
+    let stage_config = match &stage {
+        Some(stage) => {
+            let reference = StageReference {
+                project: project.to_string(),
+                stage: stage.to_string(),
+            };
+            match stage_graph_util::find_stage(&ldot_stack_config, &reference) {
+                Some(stage_config) => Some(stage_config),
+                None => return Err(format!("Stage not found: {}", reference)),
+            }
+        }
+        None => None,
+    };
+
+    let env = environment_util::get_stage_environment(&ldot_stack_config, &ldot_stack_file_name, project_config, stage_config)?;
+    Ok(env
+        .iter()
+        .map(|(key, value)| dotenv_util::format_env_line(key, value))
+        .collect())
 }
 
+// Arguments are forwarded as "${args}" or "$@", params are "name=value" pairs for the script's parameters
 pub fn execute_stack_script(
     stack: String,
     script_name: String,
-) -> Result<Vec<std::string::String>, Vec<std::string::String>> {
-    let ldot_stack_file_name: String;
-    match configuration_util::get_ldot_stack_from_stack_name(&stack) {
-        Ok(filename) => {
-            ldot_stack_file_name = filename;
-        }
+    args: Vec<String>,
+    params: Vec<String>,
+    keep_going: bool,
+    profile: Option<String>,
+) -> ExecutionReport {
+    let ldot_stack_file_name: String = match configuration_util::get_ldot_stack_from_stack_name(&stack) {
+        Ok(filename) => filename,
         Err(e) => {
             eprintln!("{}", e);
-            std::process::exit(1);
+            std::process::exit(command_execution_util::EXIT_FAILURE);
         }
-    }
+    };
 
-    let ldot_stack_config = get_ldot_stack_config(ldot_stack_file_name);
-    for script in ldot_stack_config.scripts {
+    output_util::print_verbose(format!("Stack file: {}", ldot_stack_file_name));
+    if let Err(e) = check_registered_stack(&ldot_stack_file_name, profile.clone()) {
+        return ExecutionReport::error(e, command_execution_util::EXIT_FAILURE);
+    }
+    let ldot_stack_config = get_ldot_stack_config(ldot_stack_file_name.to_string(), profile);
+    for script in &ldot_stack_config.scripts {
         if script.script_name == script_name {
-            println!("Executing {} commands", script.commands.len());
-            return execute_command_from_list(script.commands);
+            let param_values = match param_util::resolve_params(script, &params) {
+                Ok(param_values) => param_values,
+                Err(e) => return ExecutionReport::error(e, command_execution_util::EXIT_FAILURE),
+            };
+            output_util::print_info(format!("Executing {} commands", script.commands.len()));
+            let mode = match command_execution_util::resolve_execution_mode(
+                &[&ldot_stack_config.shell, &script.shell],
+                &[&ldot_stack_config.exec, &script.exec],
+            ) {
+                Ok(mode) => mode,
+                Err(e) => return ExecutionReport::error(e, command_execution_util::EXIT_FAILURE),
+            };
+            let mut scope = interpolation_util::get_script_scope(&ldot_stack_config, &ldot_stack_file_name, script);
+            scope.set_arguments(&args);
+            for (name, value) in &param_values {
+                scope.set_quoted(&format!("params.{}", name), value);
+            }
+            let mut commands: Vec<CommandConfig> = vec![];
+            for command in interpolation_util::forward_arguments(&script.commands, &args, mode.takes_arguments()) {
+                match interpolation_util::interpolate_command(&command, &scope) {
+                    Ok(command) => commands.push(command),
+                    Err(e) => {
+                        return ExecutionReport::error(
+                            format!("{} in: {}", e, script.script_name),
+                            command_execution_util::EXIT_FAILURE,
+                        )
+                    }
+                }
+            }
+            let env = match environment_util::get_script_environment(&ldot_stack_config, &ldot_stack_file_name, script, &scope) {
+                Ok(env) => env,
+                Err(e) => {
+                    return ExecutionReport::error(
+                        format!("{} in: {}", e, script.script_name),
+                        command_execution_util::EXIT_FAILURE,
+                    )
+                }
+            };
+            let context = CommandContext {
+                mode,
+                failure_policy: FailurePolicy::from_flags(keep_going, false),
+                working_directory: Some(stack_path_util::get_default_working_directory(
+                    &ldot_stack_config,
+                    &ldot_stack_file_name,
+                    None,
+                )),
+                env,
+                args,
+            };
+            return command_execution_util::execute_command_from_list(commands, &context, None);
         };
     };
     
     // If no matching script is found, you should return an error here.
-    Err(vec!["Script not found".to_string()])
-}
-
-fn execute_command_from_list(commands: Vec<String>) -> Result<Vec<String>, Vec<String>> {
-    let mut cmd_and_code: Vec<String> = vec![];
-    let mut errors_occured = false;
-
-    for command in commands {
-        let mut args: Vec<String> = command.split(" ").map(|s| s.to_string()).collect();
-        let system_command = args[0].to_string();
-
-        args.remove(0);
-        let mut cmd_execution = Command::new(&system_command);
-        cmd_execution.args(&args);
-        let happy_status_code: i32 = 0;
-        println!("\n> {}", command.purple());
-        match cmd_execution.status() {
-            Ok(exit_status) => {
-                if Some(exit_status.code()) == Some(Some(happy_status_code)) {
-                    cmd_and_code.push(
-                        format!(
-                            "\"{}\" exit code: {:?}",
-                            &command,
-                            exit_status.code().unwrap()
-                        )
-                        .bright_green()
-                        .to_string(),
-                    )
-                } else {
-                    cmd_and_code.push(
-                        format!(
-                            "\"{}\" exit code: {:?}",
-                            &command,
-                            exit_status.code().unwrap()
-                        )
-                        .yellow()
-                        .to_string(),
-                    );
-                    errors_occured = true;
-                }
-            }
-            Err(err) => {
-                println!("{}", format!("{}", err).bright_red());
-                cmd_and_code.push(
-                    format!("\"{}\": {}", &command, err)
-                        .bright_red()
-                        .to_string(),
-                );
-            }
-        }
+    ExecutionReport::error("Script not found".to_string(), command_execution_util::EXIT_FAILURE)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::jsonc_util;
+
+    // A base stack and a stack that only extends it, in a directory of their own
+    fn write_extending_stack(name: &str) -> (std::path::PathBuf, String) {
+        let directory = std::env::temp_dir().join(format!("ldot_test_{}_{}", name, std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        let base = r#"{
+    "stack_name": "base",
+    "projects": [
+        { "project_name": "api", "stages": [] }
+    ],
+    "scripts": []
+}
+"#;
+        fs::write(directory.join("base.json"), base).unwrap();
+        let stack_file = directory.join("ldot_stack.json");
+        fs::write(&stack_file, "{\n  // only the base\n  \"extends\": \"base.json\"\n}\n").unwrap();
+        (directory, stack_file.display().to_string())
     }
-    if errors_occured {
-        Err(cmd_and_code)
-    } else {
-        Ok(cmd_and_code)
+
+    #[test]
+    fn format_extending_stack() {
+        let (directory, stack_file) = write_extending_stack("fmt");
+        assert!(format_stack_file(&stack_file, false, true, false).unwrap());
+        let formatted = fs::read_to_string(&stack_file).unwrap();
+        assert_eq!(formatted, "{\n    // only the base\n    \"extends\": \"base.json\"\n}\n");
+        assert!(!format_stack_file(&stack_file, true, true, false).unwrap());
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn add_project_to_extending_stack() {
+        let (directory, stack_file) = write_extending_stack("add_project");
+        add_project(&stack_file, "web".to_string(), None, None, false).unwrap();
+        let source = fs::read_to_string(&stack_file).unwrap();
+        let value: Value = serde_json::from_str(&jsonc_util::strip_comments(&source)).unwrap();
+        assert_eq!(value.get("stack_name"), None);
+        assert_eq!(value["projects"][0]["project_name"], "web");
+        let stack_config = stack_composition_util::read_stack_config(&stack_file).unwrap();
+        let project_names: Vec<&str> = stack_config
+            .projects
+            .iter()
+            .map(|project| project.project_name.as_str())
+            .collect();
+        assert_eq!(project_names, vec!["api", "web"]);
+        assert!(add_project(&stack_file, "api".to_string(), None, None, false).is_err());
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn format_keeps_yaml_comments_unless_dropped() {
+        let directory = std::env::temp_dir().join(format!("ldot_test_yaml_comments_{}", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        let stack_file = directory.join("ldot_stack.yaml").display().to_string();
+        let source = "stack_name: demo\nprojects:\n- project_name: api # the backend\n  stages: []\nscripts: []\n";
+        fs::write(&stack_file, source).unwrap();
+        let err = format_stack_file(&stack_file, false, true, false).unwrap_err();
+        assert!(err.contains("line 3: # the backend"), "{}", err);
+        assert_eq!(fs::read_to_string(&stack_file).unwrap(), source);
+        assert!(format_stack_file(&stack_file, true, true, false).unwrap());
+        assert!(format_stack_file(&stack_file, false, true, true).unwrap());
+        assert!(!fs::read_to_string(&stack_file).unwrap().contains('#'));
+        fs::remove_dir_all(directory).unwrap();
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn hashes_in_yaml_values_are_not_comments() {
+        let source = "stack_name: \"demo # one\"\ndescription: |\n  line\n  # kept\nurl: http://host/#top\n";
+        assert_eq!(stack_format_util::find_lost_comment(source, "", StackFormat::Yaml), None);
+        let source = "stack_name: 'it''s # one' # named\n";
+        assert_eq!(
+            stack_format_util::find_lost_comment(source, "", StackFormat::Yaml),
+            Some((1, "# named".to_string()))
+        );
+    }
+}
@@ -0,0 +1,334 @@
+use directories::BaseDirs;
+use serde_json::{Map, Value};
+
+// The stack file format this version of ldot reads and writes. Files without "schema_version" are
+// version 1, files in the legacy camelCase shape are version 0.
+pub const CURRENT_SCHEMA_VERSION: u64 = 1;
+
+// The result of a migration: the converted file and what the user should know about it
+pub struct Migration {
+    pub value: Value,
+    pub notes: Vec<String>,
+}
+
+// Upgrades a stack from one schema version to the next
+struct MigrationStep {
+    from: u64,
+    migrate: fn(&Value) -> Result<Migration, String>,
+}
+
+// Every step between schema versions, in order. A new schema version adds a step here.
+const MIGRATION_STEPS: &[MigrationStep] = &[MigrationStep {
+    from: 0,
+    migrate: migrate_stack,
+}];
+
+pub fn get_schema_version(value: &Value) -> Result<u64, String> {
+    if is_legacy_stack(value) {
+        return Ok(0);
+    }
+    match value.get("schema_version") {
+        None => Ok(1),
+        Some(version) => match version.as_u64() {
+            Some(version) => Ok(version),
+            None => Err(format!("schema_version must be a whole number: {}", version)),
+        },
+    }
+}
+
+// Checks that this ldot can read the stack and returns its schema version
+pub fn check_stack_version(value: &Value) -> Result<u64, String> {
+    if let Some(min_ldot_version) = value.get("min_ldot_version") {
+        let min_ldot_version = match min_ldot_version.as_str() {
+            Some(min_ldot_version) => min_ldot_version,
+            None => return Err(format!("min_ldot_version must be a version string: {}", min_ldot_version)),
+        };
+        let required = parse_version(min_ldot_version)?;
+        if required > parse_version(env!("CARGO_PKG_VERSION"))? {
+            return Err(format!(
+                "This stack requires ldot {} or newer, this is ldot {}. Please upgrade ldot.",
+                min_ldot_version,
+                env!("CARGO_PKG_VERSION")
+            ));
+        }
+    }
+    let schema_version = get_schema_version(value)?;
+    if schema_version > CURRENT_SCHEMA_VERSION {
+        return Err(format!(
+            "This stack uses schema version {}, ldot {} supports up to version {}. Please upgrade ldot.",
+            schema_version,
+            env!("CARGO_PKG_VERSION"),
+            CURRENT_SCHEMA_VERSION
+        ));
+    }
+    Ok(schema_version)
+}
+
+// Parses "1.2.3" into comparable numbers, missing parts are 0 and pre-release suffixes are ignored
+fn parse_version(version: &str) -> Result<Vec<u64>, String> {
+    let release = version.trim().trim_start_matches('v').split(['-', '+']).next().unwrap_or_default();
+    let mut parts: Vec<u64> = vec![];
+    for part in release.split('.') {
+        match part.parse() {
+            Ok(part) => parts.push(part),
+            Err(_) => return Err(format!("Invalid version: {}", version)),
+        }
+    }
+    parts.resize(3, 0);
+    Ok(parts)
+}
+
+// Applies every migration step from the stack's schema version up to the current one
+pub fn upgrade_stack(value: &Value) -> Result<Migration, String> {
+    let mut version = check_stack_version(value)?;
+    let mut upgraded = Migration {
+        value: value.clone(),
+        notes: vec![],
+    };
+    while version < CURRENT_SCHEMA_VERSION {
+        let step = match MIGRATION_STEPS.iter().find(|step| step.from == version) {
+            Some(step) => step,
+            None => return Err(format!("No migration from schema version {}", version)),
+        };
+        let migration = (step.migrate)(&upgraded.value)?;
+        version += 1;
+        upgraded.value = set_schema_version(migration.value, version);
+        upgraded.notes.extend(migration.notes);
+    }
+    Ok(upgraded)
+}
+
+// "schema_version" goes right after "$schema", where the stack model writes it
+fn set_schema_version(value: Value, version: u64) -> Value {
+    let values = match value {
+        Value::Object(values) => values,
+        value => return value,
+    };
+    let mut versioned = Map::new();
+    if let Some(schema) = values.get("$schema") {
+        versioned.insert("$schema".to_string(), schema.clone());
+    }
+    versioned.insert("schema_version".to_string(), Value::from(version));
+    for (key, value) in values {
+        if key != "$schema" && key != "schema_version" {
+            versioned.insert(key, value);
+        }
+    }
+    Value::Object(versioned)
+}
+
+pub fn is_legacy_config(value: &Value) -> bool {
+    value.get("defaultStack").is_some() || value.get("registeredStackFiles").is_some()
+}
+
+// Legacy stacks use "stackName" and key projects by name, e.g. { "project1": [ { "stage": "start" } ] }. A current
+// project that lacks its "project_name" is not legacy, validation reports it instead.
+pub fn is_legacy_stack(value: &Value) -> bool {
+    if value.get("stackName").is_some() {
+        return true;
+    }
+    match value.get("projects") {
+        Some(Value::Object(_)) => true,
+        Some(Value::Array(projects)) => projects.iter().any(is_legacy_project),
+        _ => false,
+    }
+}
+
+// A legacy project maps its name to stages that have a "stage" key
+fn is_legacy_project(project: &Value) -> bool {
+    let entries = match project.as_object() {
+        Some(entries) if !entries.contains_key("project_name") => entries,
+        _ => return false,
+    };
+    entries.values().filter_map(Value::as_array).flatten().any(|stage| stage.get("stage").is_some())
+}
+
+// Converts "defaultStack" and "registeredStackFiles", expanding "~/" since stack files are opened as written
+pub fn migrate_config(value: &Value) -> Result<Migration, String> {
+    let mut notes: Vec<String> = vec![];
+    let default_stack = value.get("defaultStack").and_then(Value::as_str).unwrap_or_default();
+    let mut registered_stack_files: Vec<Value> = vec![];
+    for stack_file in value
+        .get("registeredStackFiles")
+        .and_then(Value::as_array)
+        .cloned()
+        .unwrap_or_default()
+    {
+        let stack_file = match stack_file.as_str() {
+            Some(stack_file) => stack_file.to_string(),
+            None => return Err(format!("Expected a file name in registeredStackFiles: {}", stack_file)),
+        };
+        let expanded = expand_home_directory(&stack_file);
+        if expanded != stack_file {
+            notes.push(format!("Expanded {} to {}", stack_file, expanded));
+        }
+        registered_stack_files.push(Value::String(expanded));
+    }
+
+    let mut config = Map::new();
+    config.insert("default_stack".to_string(), Value::String(default_stack.to_string()));
+    config.insert("registered_stack_files".to_string(), Value::Array(registered_stack_files));
+    Ok(Migration {
+        value: Value::Object(config),
+        notes,
+    })
+}
+
+fn expand_home_directory(path: &str) -> String {
+    match (path.strip_prefix("~/"), BaseDirs::new()) {
+        (Some(rest), Some(base_dirs)) => base_dirs.home_dir().join(rest).display().to_string(),
+        _ => path.to_string(),
+    }
+}
+
+// Schema version 0 to 1: converts a legacy stack to the current shape. Other keys are kept as they are.
+fn migrate_stack(value: &Value) -> Result<Migration, String> {
+    let legacy = match value.as_object() {
+        Some(legacy) => legacy,
+        None => return Err("Expected the stack file to contain an object".to_string()),
+    };
+    let mut notes: Vec<String> = vec![];
+    let mut stack = Map::new();
+    for (key, value) in legacy {
+        match key.as_str() {
+            "stackName" => {
+                stack.insert("stack_name".to_string(), value.clone());
+            }
+            "projects" => {
+                stack.insert("projects".to_string(), migrate_projects(value, &mut notes)?);
+            }
+            "modules" => {
+                let modules: Vec<String> = value
+                    .as_array()
+                    .map(|modules| modules.iter().map(|module| module.to_string()).collect())
+                    .unwrap_or_default();
+                notes.push(format!(
+                    "Removed \"modules\", stacks no longer declare the tools they need: {}",
+                    modules.join(", ")
+                ));
+            }
+            _ => {
+                stack.insert(key.to_string(), value.clone());
+            }
+        }
+    }
+    if !stack.contains_key("scripts") {
+        stack.insert("scripts".to_string(), Value::Array(vec![]));
+    }
+    Ok(Migration {
+        value: Value::Object(stack),
+        notes,
+    })
+}
+
+fn migrate_projects(projects: &Value, notes: &mut Vec<String>) -> Result<Value, String> {
+    // Projects stored as one map are the same as an array holding that map
+    let projects = match projects {
+        Value::Array(projects) => projects.clone(),
+        Value::Object(_) => vec![projects.clone()],
+        _ => return Err("Expected \"projects\" to be an array".to_string()),
+    };
+    let mut migrated: Vec<Value> = vec![];
+    for project in &projects {
+        // Projects that already have the current shape are kept
+        if project.get("project_name").is_some() {
+            migrated.push(project.clone());
+            continue;
+        }
+        let entries = match project.as_object() {
+            Some(entries) => entries,
+            None => return Err(format!("Expected a project object: {}", project)),
+        };
+        for (project_name, stages) in entries {
+            let stages = match stages.as_array() {
+                Some(stages) => stages,
+                None => return Err(format!("Expected the stages of {} to be an array", project_name)),
+            };
+            let mut migrated_stages: Vec<Value> = vec![];
+            for stage in stages {
+                migrated_stages.push(migrate_stage(project_name, stage, notes)?);
+            }
+            let mut migrated_project = Map::new();
+            migrated_project.insert("project_name".to_string(), Value::String(project_name.to_string()));
+            migrated_project.insert("stages".to_string(), Value::Array(migrated_stages));
+            migrated.push(Value::Object(migrated_project));
+        }
+    }
+    Ok(Value::Array(migrated))
+}
+
+// "stage" becomes "stage_name". A name such as "clone|fetch" becomes the stage "clone" with the alias "fetch", so
+// both names can still be executed and used as prerequisites.
+fn migrate_stage(project_name: &str, stage: &Value, notes: &mut Vec<String>) -> Result<Value, String> {
+    let legacy = match stage.as_object() {
+        Some(legacy) => legacy,
+        None => return Err(format!("Expected a stage object in {}: {}", project_name, stage)),
+    };
+    let stage_names: Vec<String> = match legacy.get("stage").or_else(|| legacy.get("stage_name")) {
+        Some(Value::String(names)) => names.split('|').map(|name| name.trim().to_string()).collect(),
+        _ => return Err(format!("Expected a stage name in {}: {}", project_name, stage)),
+    };
+
+    let mut migrated = Map::new();
+    migrated.insert("stage_name".to_string(), Value::String(stage_names[0].to_string()));
+    if stage_names.len() > 1 {
+        notes.push(format!(
+            "Split \"{}\" of {}: stage \"{}\" with the aliases \"{}\"",
+            stage_names.join("|"),
+            project_name,
+            stage_names[0],
+            stage_names[1..].join("\", \"")
+        ));
+        let aliases = stage_names[1..].iter().map(|alias| Value::String(alias.to_string())).collect();
+        migrated.insert("aliases".to_string(), Value::Array(aliases));
+    }
+    for (key, value) in legacy {
+        if key != "stage" && key != "stage_name" {
+            migrated.insert(key.to_string(), value.clone());
+        }
+    }
+    Ok(Value::Object(migrated))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn legacy_stacks_are_detected_by_their_shape() {
+        assert!(is_legacy_stack(&json!({ "stackName": "demo", "projects": [] })));
+        assert!(is_legacy_stack(&json!({ "projects": { "api": [{ "stage": "build" }] } })));
+        assert!(is_legacy_stack(&json!({ "projects": [{ "api": [{ "stage": "build" }] }] })));
+        assert!(!is_legacy_stack(&json!({ "stack_name": "demo", "projects": [{ "project_name": "api" }] })));
+        // A current project without its name is a mistake to report, not a legacy stack
+        let unnamed = json!({ "stack_name": "demo", "projects": [{ "stages": [{ "stage_name": "build" }] }] });
+        assert!(!is_legacy_stack(&unnamed));
+        assert_eq!(get_schema_version(&unnamed), Ok(1));
+    }
+
+    #[test]
+    fn migration_keeps_unknown_keys() {
+        let legacy = json!({
+            "stackName": "demo",
+            "modules": ["git"],
+            "env": { "A": "1" },
+            "projects": { "api": [{ "stage": "clone|fetch", "commands": ["git clone"] }] }
+        });
+        let migration = upgrade_stack(&legacy).unwrap();
+        assert_eq!(
+            migration.value,
+            json!({
+                "schema_version": 1,
+                "stack_name": "demo",
+                "env": { "A": "1" },
+                "projects": [{
+                    "project_name": "api",
+                    "stages": [{ "stage_name": "clone", "aliases": ["fetch"], "commands": ["git clone"] }]
+                }],
+                "scripts": []
+            })
+        );
+        assert_eq!(migration.notes.len(), 2);
+    }
+}
@@ -0,0 +1,78 @@
+use std::fmt::Display;
+use std::io::IsTerminal;
+use std::sync::atomic::{AtomicU8, Ordering};
+
+use clap::ValueEnum;
+use colored::Colorize;
+
+// When output is colored. Auto colors it when stdout is a terminal, and follows NO_COLOR and CLICOLOR_FORCE.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ColorChoice {
+    Auto,
+    Always,
+    Never,
+}
+
+// How much ldot prints besides the output of the commands it runs
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verbosity {
+    Quiet,
+    Normal,
+    Verbose,
+}
+
+// Set once from the global --quiet and --verbose flags before any command runs
+static VERBOSITY: AtomicU8 = AtomicU8::new(Verbosity::Normal as u8);
+
+pub fn set_verbosity(verbosity: Verbosity) {
+    VERBOSITY.store(verbosity as u8, Ordering::Relaxed);
+}
+
+pub fn get_verbosity() -> Verbosity {
+    match VERBOSITY.load(Ordering::Relaxed) {
+        0 => Verbosity::Quiet,
+        2 => Verbosity::Verbose,
+        _ => Verbosity::Normal,
+    }
+}
+
+pub fn is_quiet() -> bool {
+    get_verbosity() == Verbosity::Quiet
+}
+
+pub fn is_verbose() -> bool {
+    get_verbosity() == Verbosity::Verbose
+}
+
+pub fn set_color(color: ColorChoice) {
+    match color {
+        ColorChoice::Always => colored::control::set_override(true),
+        ColorChoice::Never => colored::control::set_override(false),
+        ColorChoice::Auto => {
+            if !std::io::stdout().is_terminal() && std::env::var_os("CLICOLOR_FORCE").is_none() {
+                colored::control::set_override(false);
+            }
+        }
+    }
+}
+
+// Prints a "--- Title ---" header, left out with --quiet
+pub fn print_header(title: &str) {
+    if !is_quiet() {
+        println!("{}", format!("--- {} ---", title).blue());
+    }
+}
+
+// Prints what ldot is doing, left out with --quiet
+pub fn print_info(message: impl Display) {
+    if !is_quiet() {
+        println!("{}", message);
+    }
+}
+
+// Prints details that are only useful when looking into a problem, shown with --verbose
+pub fn print_verbose(message: impl Display) {
+    if is_verbose() {
+        println!("{}", message.to_string().dimmed());
+    }
+}
@@ -0,0 +1,77 @@
+use std::collections::BTreeMap;
+
+use serde_json::Value;
+
+use crate::models::stack_config_json::{ParamConfig, ParamType, ScriptConfig};
+
+// Checks a value given for a parameter against its type. Numbers and booleans are written the way JSON writes them.
+pub fn check_param_value(param: &ParamConfig, value: &str) -> Result<(), String> {
+    let valid = match param.param_type {
+        ParamType::String => true,
+        ParamType::Number => value.parse::<f64>().is_ok_and(f64::is_finite),
+        ParamType::Boolean => value == "true" || value == "false",
+    };
+    if !valid {
+        return Err(format!(
+            "Parameter {} must be a {}: {}",
+            param.param_name, param.param_type, value
+        ));
+    }
+    Ok(())
+}
+
+// The default of a parameter as it is used in commands, e.g. 3 or true. Defaults must be a string, number or boolean.
+pub fn get_default_value(param: &ParamConfig) -> Result<Option<String>, String> {
+    let value = match &param.default {
+        None => return Ok(None),
+        Some(Value::String(value)) => value.to_string(),
+        Some(value @ (Value::Number(_) | Value::Bool(_))) => value.to_string(),
+        Some(value) => {
+            return Err(format!(
+                "Default of parameter {} must be a string, number or boolean: {}",
+                param.param_name, value
+            ))
+        }
+    };
+    check_param_value(param, &value).map_err(|e| format!("Invalid default, {}", e.to_lowercase()))?;
+    Ok(Some(value))
+}
+
+// Resolves "--param name=value" arguments against the script's params. Unknown parameters, values of the wrong
+// type and parameters without a value or a default are errors.
+pub fn resolve_params(script: &ScriptConfig, given: &[String]) -> Result<BTreeMap<String, String>, String> {
+    let mut values: BTreeMap<String, String> = BTreeMap::new();
+    for argument in given {
+        let (name, value) = match argument.split_once('=') {
+            Some((name, value)) => (name.trim(), value),
+            None => return Err(format!("Expected --param name=value: {}", argument)),
+        };
+        let param = match script.params.iter().find(|param| param.param_name == name) {
+            Some(param) => param,
+            None => return Err(format!("Unknown parameter of script {}: {}", script.script_name, name)),
+        };
+        check_param_value(param, value)?;
+        values.insert(name.to_string(), value.to_string());
+    }
+
+    let mut missing: Vec<&str> = vec![];
+    for param in &script.params {
+        if values.contains_key(&param.param_name) {
+            continue;
+        }
+        match get_default_value(param)? {
+            Some(value) => {
+                values.insert(param.param_name.to_string(), value);
+            }
+            None => missing.push(&param.param_name),
+        }
+    }
+    if !missing.is_empty() {
+        return Err(format!(
+            "Missing parameters of script {}, set them with --param name=value: {}",
+            script.script_name,
+            missing.join(", ")
+        ));
+    }
+    Ok(values)
+}
@@ -0,0 +1,122 @@
+use schemars::schema_for;
+use serde_json::{json, Value};
+
+use crate::models::ldot_config_json::Configuration;
+use crate::models::stack_config_json::StackConfig;
+use crate::utils::stack_path_util;
+
+// Written next to generated stack files and referenced from their "$schema" key
+pub const STACK_SCHEMA_FILE_NAME: &str = "ldot_stack.schema.json";
+
+// The schemas are derived from the serde types, so they always match what ldot reads. Stacks that extend or include
+// other files get their required keys from those files.
+pub fn get_stack_schema() -> String {
+    let mut schema = serde_json::to_value(schema_for!(StackConfig)).unwrap();
+    if let Some(required) = schema.as_object_mut().and_then(|schema| schema.remove("required")) {
+        schema["if"] = json!({ "not": { "anyOf": [{ "required": ["extends"] }, { "required": ["include"] }] } });
+        schema["then"] = json!({ "required": required });
+    }
+    serde_json::to_string_pretty(&schema).unwrap()
+}
+
+// Local overrides such as ldot_stack.local.json only contain the values they change, so no key is required at the top
+pub fn get_partial_stack_schema() -> String {
+    let mut schema = serde_json::to_value(schema_for!(StackConfig)).unwrap();
+    if let Some(schema) = schema.as_object_mut() {
+        schema.remove("required");
+    }
+    serde_json::to_string_pretty(&schema).unwrap()
+}
+
+pub fn get_configuration_schema() -> String {
+    serde_json::to_string_pretty(&schema_for!(Configuration)).unwrap()
+}
+
+// Writes the stack schema next to a stack file and returns the relative reference for its "$schema" key
+pub fn write_stack_schema(stack_file: &str) -> Result<String, std::io::Error> {
+    let schema_file = stack_path_util::get_stack_directory(stack_file).join(STACK_SCHEMA_FILE_NAME);
+    std::fs::write(&schema_file, get_stack_schema() + "\n")?;
+    Ok(format!("./{}", STACK_SCHEMA_FILE_NAME))
+}
+
+// Removes every optional key whose value is the default recorded in the stack schema, e.g. empty descriptions
+pub fn remove_default_values(value: &mut Value) {
+    let schema = serde_json::to_value(schema_for!(StackConfig)).unwrap();
+    let definitions = schema.get("definitions").cloned().unwrap_or(Value::Null);
+    remove_defaults_with(value, &schema, &definitions);
+}
+
+fn remove_defaults_with(value: &mut Value, schema: &Value, definitions: &Value) {
+    let mut schema = resolve_reference(schema, definitions);
+    // Untagged enums such as commands are described by "anyOf", for objects the object variant applies
+    if let Some(variants) = schema.get("anyOf").and_then(Value::as_array) {
+        let object_variant = variants
+            .iter()
+            .map(|variant| resolve_reference(variant, definitions))
+            .find(|variant| variant.get("properties").is_some());
+        match object_variant {
+            Some(variant) if value.is_object() => schema = variant,
+            _ => return,
+        }
+    }
+    match value {
+        Value::Object(values) => {
+            let properties = match schema.get("properties").and_then(Value::as_object) {
+                Some(properties) => properties,
+                None => return,
+            };
+            let required: Vec<&str> = schema
+                .get("required")
+                .and_then(Value::as_array)
+                .map(|required| required.iter().filter_map(Value::as_str).collect())
+                .unwrap_or_default();
+            values.retain(|key, value| {
+                required.contains(&key.as_str())
+                    || properties
+                        .get(key)
+                        .and_then(|property| property.get("default"))
+                        .is_none_or(|default| default != value)
+            });
+            for (key, value) in values.iter_mut() {
+                if let Some(property) = properties.get(key) {
+                    remove_defaults_with(value, property, definitions);
+                }
+            }
+        }
+        Value::Array(values) => {
+            if let Some(items) = schema.get("items") {
+                for value in values {
+                    remove_defaults_with(value, items, definitions);
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+fn resolve_reference<'a>(schema: &'a Value, definitions: &'a Value) -> &'a Value {
+    match schema
+        .get("$ref")
+        .and_then(Value::as_str)
+        .and_then(|reference| reference.strip_prefix("#/definitions/"))
+    {
+        Some(name) => definitions.get(name).unwrap_or(schema),
+        None => schema,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn extending_stacks_need_no_required_keys() {
+        let schema: Value = serde_json::from_str(&get_stack_schema()).unwrap();
+        assert_eq!(schema.get("required"), None);
+        assert_eq!(schema["then"]["required"], json!(["projects", "scripts", "stack_name"]));
+        assert_eq!(schema["if"]["not"]["anyOf"][0]["required"], json!(["extends"]));
+        let partial: Value = serde_json::from_str(&get_partial_stack_schema()).unwrap();
+        assert_eq!(partial.get("required"), None);
+        assert!(partial.get("if").is_none());
+    }
+}
@@ -0,0 +1,424 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde_json::{Map, Value};
+
+use crate::models::stack_config_json::StackConfig;
+use crate::utils::{json_source_util, migration_util};
+use crate::utils::stack_format_util::{self, StackParseError};
+
+// Where part of a composed stack was read from, e.g. "projects[2]" of the stack is "" of "api/ldot_project.json"
+pub struct StackOrigin {
+    pub path: String,
+    pub file: String,
+    pub source_path: String,
+}
+
+// An error in one of the files of a composed stack
+pub struct CompositionError {
+    pub file: String,
+    pub error: StackParseError,
+}
+
+pub struct ComposedStack {
+    pub value: Value,
+    pub origins: Vec<StackOrigin>,
+    // The source of every file that was read, by file name
+    pub sources: BTreeMap<String, String>,
+}
+
+struct Composer {
+    // Stack files being composed, to detect cycles
+    chain: Vec<(PathBuf, String)>,
+    // Where each named part of the stack came from, e.g. "projects/api/stages/build". Later files override earlier ones.
+    origins: BTreeMap<String, (String, String)>,
+    sources: BTreeMap<String, String>,
+}
+
+// Stacks that extend a base stack, include project files or have a local override file
+pub fn is_composed(stack_file: &str, value: &Value) -> bool {
+    value.get("extends").is_some() || value.get("include").is_some() || find_local_file(stack_file).is_some()
+}
+
+// Files that extend a base stack or include project files, and local override files, only hold part of a stack
+pub fn is_partial(stack_file: &str, value: &Value) -> bool {
+    let local = Path::new(stack_file)
+        .file_stem()
+        .is_some_and(|stem| stem.to_string_lossy().ends_with(".local"));
+    value.get("extends").is_some() || value.get("include").is_some() || local
+}
+
+// "ldot_stack.json" is overridden by an untracked "ldot_stack.local.json" next to it, in any of the stack formats
+pub fn find_local_file(stack_file: &str) -> Option<String> {
+    let path = Path::new(stack_file);
+    let stem = path.file_stem()?.to_string_lossy();
+    if stem.ends_with(".local") {
+        return None;
+    }
+    ["json", "jsonc", "json5", "yaml", "yml", "toml"]
+        .iter()
+        .map(|extension| path.with_file_name(format!("{}.local.{}", stem, extension)))
+        .find(|local_file| local_file.is_file())
+        .map(|local_file| local_file.display().to_string())
+}
+
+// Reads a stack file with everything it extends and includes
+pub fn read_stack_config(stack_file: &str) -> Result<StackConfig, String> {
+    let source = match fs::read_to_string(stack_file) {
+        Ok(source) => source,
+        Err(e) => return Err(format!("Could not open and read stack file: {} {}", stack_file, e)),
+    };
+    let format = stack_format_util::get_stack_format(stack_file);
+    let composed =
+        stack_format_util::parse_stack_value(&source, format).is_ok_and(|value| is_composed(stack_file, &value));
+    if !composed {
+        return stack_format_util::read_stack_config(stack_file);
+    }
+    let composed = compose_stack(stack_file)
+        .map_err(|e| format!("Could not parse stack file: {} {}", e.file, e.error.message))?;
+    serde_json::from_value(composed.value).map_err(|e| format!("Could not parse stack file: {} {}", stack_file, e))
+}
+
+// The name of a stack, read without checking the rest of it so a stack with errors is still found by its name.
+// Composed stacks are merged first, the name may come from the stack they extend or their local overrides.
+pub fn read_stack_name(stack_file: &str) -> Result<String, String> {
+    let source = match fs::read_to_string(stack_file) {
+        Ok(source) => source,
+        Err(e) => return Err(format!("Could not open and read stack file: {} {}", stack_file, e)),
+    };
+    let value = stack_format_util::parse_stack_value(&source, stack_format_util::get_stack_format(stack_file))
+        .map_err(|e| format!("Could not parse stack file: {} {}", stack_file, e.message))?;
+    let value = match is_composed(stack_file, &value) {
+        true => {
+            compose_stack(stack_file)
+                .map_err(|e| format!("Could not parse stack file: {} {}", e.file, e.error.message))?
+                .value
+        }
+        false => value,
+    };
+    match value.get("stack_name").and_then(Value::as_str) {
+        Some(stack_name) => Ok(stack_name.to_string()),
+        None => Err(format!("Stack file has no stack_name: {}", stack_file)),
+    }
+}
+
+// Merges the stack a file extends, the project files it includes, the file itself and its local overrides, in that order
+pub fn compose_stack(stack_file: &str) -> Result<ComposedStack, CompositionError> {
+    let mut composer = Composer {
+        chain: vec![],
+        origins: BTreeMap::new(),
+        sources: BTreeMap::new(),
+    };
+    let mut value = composer.compose_file(stack_file)?;
+    if let Some(local_file) = find_local_file(stack_file) {
+        // Local overrides may set only what they change, e.g. { "variables": { "api_port": "9091" } }
+        let local = composer.read_value(&local_file)?;
+        if !local.is_object() {
+            return Err(get_error(&local_file, "Expected the local stack file to contain an object".to_string()));
+        }
+        composer.record_stack_origins(&local_file, &local);
+        merge_values(&mut value, local);
+    }
+    if let Some(values) = value.as_object_mut() {
+        values.retain(|key, _| key != "extends" && key != "include");
+    }
+    let origins = composer.get_origins(&value);
+    Ok(ComposedStack {
+        value,
+        origins,
+        sources: composer.sources,
+    })
+}
+
+fn get_error(file: &str, message: String) -> CompositionError {
+    CompositionError {
+        file: file.to_string(),
+        error: StackParseError {
+            message,
+            line: None,
+            column: None,
+        },
+    }
+}
+
+// Files named in a stack are relative to the directory of that stack
+fn get_relative_file(file: &str, relative: &str) -> String {
+    match Path::new(file).parent() {
+        Some(parent) => parent.join(relative).display().to_string(),
+        None => relative.to_string(),
+    }
+}
+
+// The absolute directory of a file
+fn get_directory(file: &str) -> PathBuf {
+    fs::canonicalize(file)
+        .ok()
+        .and_then(|file| file.parent().map(Path::to_path_buf))
+        .unwrap_or_else(|| PathBuf::from("."))
+}
+
+// Makes the relative "root", project "path" and "env_files" of a base stack absolute, anchored where the base stack
+// resolves them, so they don't move to the directory of the stack that extends it. "env_files" that start with a
+// variable are left alone.
+fn anchor_paths(value: &mut Value, directory: &Path) {
+    let root = anchor_path(value, "root", directory).unwrap_or_else(|| directory.to_path_buf());
+    anchor_env_files(value, &root);
+    for project in value.get_mut("projects").and_then(Value::as_array_mut).into_iter().flatten() {
+        let project_directory = anchor_path(project, "path", &root).unwrap_or_else(|| root.to_path_buf());
+        anchor_env_files(project, &project_directory);
+        for stage in project.get_mut("stages").and_then(Value::as_array_mut).into_iter().flatten() {
+            anchor_env_files(stage, &project_directory);
+        }
+    }
+    for script in value.get_mut("scripts").and_then(Value::as_array_mut).into_iter().flatten() {
+        anchor_env_files(script, &root);
+    }
+}
+
+// Anchors the path under a key and returns it
+fn anchor_path(value: &mut Value, key: &str, directory: &Path) -> Option<PathBuf> {
+    let path = value.get_mut(key)?;
+    let anchored = directory.join(path.as_str()?);
+    *path = Value::String(anchored.display().to_string());
+    Some(anchored)
+}
+
+fn anchor_env_files(value: &mut Value, directory: &Path) {
+    for env_file in value.get_mut("env_files").and_then(Value::as_array_mut).into_iter().flatten() {
+        if let Some(file) = env_file.as_str().filter(|file| !file.starts_with("${")) {
+            *env_file = Value::String(directory.join(file).display().to_string());
+        }
+    }
+}
+
+// Objects are merged key by key and projects, stages, scripts and profiles by their name. Anything else replaces the base.
+fn merge_values(base: &mut Value, overlay: Value) {
+    let (base, overlay) = match (base, overlay) {
+        (Value::Object(base), Value::Object(overlay)) => (base, overlay),
+        (base, overlay) => {
+            *base = overlay;
+            return;
+        }
+    };
+    for (key, value) in overlay {
+        let name_key = match key.as_str() {
+            "projects" => Some("project_name"),
+            "stages" => Some("stage_name"),
+            "scripts" => Some("script_name"),
+            "profiles" => Some("profile_name"),
+            _ => None,
+        };
+        match (base.get_mut(&key), name_key, value) {
+            (Some(Value::Array(items)), Some(name_key), Value::Array(overlay_items)) => {
+                merge_named_items(items, overlay_items, name_key);
+            }
+            (Some(existing @ Value::Object(_)), _, value @ Value::Object(_)) => merge_values(existing, value),
+            (_, _, value) => {
+                base.insert(key, value);
+            }
+        }
+    }
+}
+
+fn merge_named_items(items: &mut Vec<Value>, overlay_items: Vec<Value>, name_key: &str) {
+    for overlay_item in overlay_items {
+        let name = overlay_item.get(name_key).cloned();
+        match items
+            .iter_mut()
+            .find(|item| name.is_some() && item.get(name_key) == name.as_ref())
+        {
+            Some(item) => merge_values(item, overlay_item),
+            None => items.push(overlay_item),
+        }
+    }
+}
+
+fn get_name(value: &Value, name_key: &str) -> String {
+    value.get(name_key).and_then(Value::as_str).unwrap_or_default().to_string()
+}
+
+impl Composer {
+    fn read_value(&mut self, file: &str) -> Result<Value, CompositionError> {
+        let source = match fs::read_to_string(file) {
+            Ok(source) => source,
+            Err(e) => return Err(get_error(file, format!("Could not open and read file: {}", e))),
+        };
+        let value = stack_format_util::parse_stack_value(&source, stack_format_util::get_stack_format(file))
+            .map_err(|error| CompositionError {
+                file: file.to_string(),
+                error,
+            })?;
+        self.sources.insert(file.to_string(), source);
+        Ok(value)
+    }
+
+    fn compose_file(&mut self, file: &str) -> Result<Value, CompositionError> {
+        let mut value = self.read_value(file)?;
+        let canonical = fs::canonicalize(file).unwrap_or_else(|_| PathBuf::from(file));
+        if self.chain.iter().any(|(chained, _)| *chained == canonical) {
+            let mut cycle: Vec<String> = self.chain.iter().map(|(_, name)| name.to_string()).collect();
+            cycle.push(file.to_string());
+            return Err(get_error(file, format!("Stack extends itself: {}", cycle.join(" -> "))));
+        }
+
+        // Every stack file in the chain is checked and upgraded on its own
+        match migration_util::check_stack_version(&value) {
+            Ok(version) if version < migration_util::CURRENT_SCHEMA_VERSION => {
+                value = migration_util::upgrade_stack(&value).map_err(|e| get_error(file, e))?.value;
+            }
+            Ok(_) => {}
+            Err(e) => return Err(get_error(file, e)),
+        }
+        if !value.is_object() {
+            return Err(get_error(file, "Expected the stack file to contain an object".to_string()));
+        }
+
+        self.chain.push((canonical, file.to_string()));
+        let mut composed = match value.get("extends") {
+            Some(Value::String(base)) => {
+                let base_file = get_relative_file(file, base);
+                let mut composed = self.compose_file(&base_file)?;
+                anchor_paths(&mut composed, &get_directory(&base_file));
+                composed
+            }
+            Some(base) => return Err(get_error(file, format!("extends must be a file name: {}", base))),
+            None => Value::Object(Map::new()),
+        };
+        self.chain.pop();
+
+        let includes: Vec<String> = match value.get("include") {
+            Some(Value::Array(includes)) => includes
+                .iter()
+                .map(|include| match include.as_str() {
+                    Some(include) => Ok(get_relative_file(file, include)),
+                    None => Err(get_error(file, format!("include must list file names: {}", include))),
+                })
+                .collect::<Result<_, _>>()?,
+            Some(includes) => return Err(get_error(file, format!("include must list file names: {}", includes))),
+            None => vec![],
+        };
+        let mut projects: Vec<Value> = vec![];
+        for include in includes {
+            projects.push(self.read_project(&include)?);
+        }
+        let mut included = Map::new();
+        included.insert("projects".to_string(), Value::Array(projects));
+        merge_values(&mut composed, Value::Object(included));
+
+        self.record_stack_origins(file, &value);
+        merge_values(&mut composed, value);
+        Ok(composed)
+    }
+
+    // Included files contain one project. Its "path" is relative to the file, the project's directory by default.
+    fn read_project(&mut self, file: &str) -> Result<Value, CompositionError> {
+        let mut value = self.read_value(file)?;
+        stack_format_util::parse_project_config(&self.sources[file], stack_format_util::get_stack_format(file))
+            .map_err(|error| CompositionError {
+                file: file.to_string(),
+                error,
+            })?;
+
+        let directory = get_directory(file);
+        let path = match value.get("path").and_then(Value::as_str) {
+            Some(path) => directory.join(path),
+            None => directory,
+        };
+        value["path"] = Value::String(path.display().to_string());
+
+        self.record_project_origins(file, "", &value);
+        Ok(value)
+    }
+
+    fn record(&mut self, name: String, file: &str, source_path: String) {
+        self.origins.insert(name, (file.to_string(), source_path));
+    }
+
+    fn record_stack_origins(&mut self, file: &str, value: &Value) {
+        for (key, item) in value.as_object().into_iter().flatten() {
+            self.record(key.to_string(), file, key.to_string());
+            match (key.as_str(), item) {
+                ("projects", Value::Array(projects)) => {
+                    for (index, project) in projects.iter().enumerate() {
+                        self.record_project_origins(file, &format!("projects[{}]", index), project);
+                    }
+                }
+                ("scripts" | "profiles", Value::Array(items)) => {
+                    let name_key = if key == "scripts" { "script_name" } else { "profile_name" };
+                    for (index, item) in items.iter().enumerate() {
+                        let name = format!("{}/{}", key, get_name(item, name_key));
+                        self.record(name, file, format!("{}[{}]", key, index));
+                    }
+                }
+                ("variables" | "env" | "groups", Value::Object(values)) => {
+                    for name in values.keys() {
+                        let name = format!("{}.{}", key, name);
+                        self.record(name.to_string(), file, name);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn record_project_origins(&mut self, file: &str, source_path: &str, project: &Value) {
+        let project_name = format!("projects/{}", get_name(project, "project_name"));
+        self.record(project_name.to_string(), file, source_path.to_string());
+        let stages = project.get("stages").and_then(Value::as_array).into_iter().flatten();
+        for (index, stage) in stages.enumerate() {
+            let name = format!("{}/stages/{}", project_name, get_name(stage, "stage_name"));
+            self.record(name, file, json_source_util::join_path(source_path, &format!("stages[{}]", index)));
+        }
+    }
+
+    // The origins of the composed stack by the paths diagnostics use, e.g. "projects[2].stages[0]"
+    fn get_origins(&self, value: &Value) -> Vec<StackOrigin> {
+        let mut named: Vec<(String, String)> = vec![];
+        for (key, item) in value.as_object().into_iter().flatten() {
+            named.push((key.to_string(), key.to_string()));
+            match (key.as_str(), item) {
+                ("projects", Value::Array(projects)) => {
+                    for (index, project) in projects.iter().enumerate() {
+                        let project_name = format!("projects/{}", get_name(project, "project_name"));
+                        let project_path = format!("projects[{}]", index);
+                        let stages = project.get("stages").and_then(Value::as_array).into_iter().flatten();
+                        for (stage_index, stage) in stages.enumerate() {
+                            named.push((
+                                format!("{}/stages/{}", project_name, get_name(stage, "stage_name")),
+                                format!("{}.stages[{}]", project_path, stage_index),
+                            ));
+                        }
+                        named.push((project_name, project_path));
+                    }
+                }
+                ("scripts" | "profiles", Value::Array(items)) => {
+                    let name_key = if key == "scripts" { "script_name" } else { "profile_name" };
+                    for (index, item) in items.iter().enumerate() {
+                        named.push((
+                            format!("{}/{}", key, get_name(item, name_key)),
+                            format!("{}[{}]", key, index),
+                        ));
+                    }
+                }
+                ("variables" | "env" | "groups", Value::Object(values)) => {
+                    for name in values.keys() {
+                        let name = format!("{}.{}", key, name);
+                        named.push((name.to_string(), name));
+                    }
+                }
+                _ => {}
+            }
+        }
+        named
+            .into_iter()
+            .filter_map(|(name, path)| {
+                self.origins.get(&name).map(|(file, source_path)| StackOrigin {
+                    path,
+                    file: file.to_string(),
+                    source_path: source_path.to_string(),
+                })
+            })
+            .collect()
+    }
+}
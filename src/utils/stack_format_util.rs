@@ -0,0 +1,277 @@
+use std::path::{Path, PathBuf};
+
+use clap::ValueEnum;
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+
+use crate::models::stack_config_json::{ProjectConfig, StackConfig};
+use crate::utils::jsonc_util::{self, KeyOrder};
+use crate::utils::{migration_util, schema_util};
+
+// Stack files use the same model in every format, the format is picked from the file extension
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum StackFormat {
+    Json,
+    Json5,
+    Yaml,
+    Toml,
+}
+
+// A parse error with the 1-based position reported by the parser, when it has one
+pub struct StackParseError {
+    pub message: String,
+    pub line: Option<usize>,
+    pub column: Option<usize>,
+}
+
+// ".json5" is JSON5, ".yaml" and ".yml" are YAML, ".toml" is TOML, everything else is read as JSON with comments
+pub fn get_stack_format(stack_file: &str) -> StackFormat {
+    let extension = Path::new(stack_file)
+        .extension()
+        .map(|extension| extension.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+    match extension.as_str() {
+        "json5" => StackFormat::Json5,
+        "yaml" | "yml" => StackFormat::Yaml,
+        "toml" => StackFormat::Toml,
+        _ => StackFormat::Json,
+    }
+}
+
+pub fn get_default_file_name(format: StackFormat) -> &'static str {
+    match format {
+        StackFormat::Json => "ldot_stack.json",
+        StackFormat::Json5 => "ldot_stack.json5",
+        StackFormat::Yaml => "ldot_stack.yaml",
+        StackFormat::Toml => "ldot_stack.toml",
+    }
+}
+
+// The stack file in the current directory, in the order json, jsonc, json5, yaml, yml, toml. Defaults to ldot_stack.json.
+pub fn find_default_stack_file() -> PathBuf {
+    [
+        "ldot_stack.json",
+        "ldot_stack.jsonc",
+        "ldot_stack.json5",
+        "ldot_stack.yaml",
+        "ldot_stack.yml",
+        "ldot_stack.toml",
+    ]
+        .iter()
+        .map(PathBuf::from)
+        .find(|file| file.is_file())
+        .unwrap_or_else(|| PathBuf::from("ldot_stack.json"))
+}
+
+// Stacks in an older schema version are upgraded in memory, the file itself is only changed by "ldot migrate"
+pub fn parse_stack_config(source: &str, format: StackFormat) -> Result<StackConfig, StackParseError> {
+    let value: Value = parse_source(source, format)?;
+    let to_parse_error = |message: String| StackParseError {
+        message,
+        line: None,
+        column: None,
+    };
+    if migration_util::check_stack_version(&value).map_err(to_parse_error)? < migration_util::CURRENT_SCHEMA_VERSION {
+        let migration = migration_util::upgrade_stack(&value).map_err(to_parse_error)?;
+        return serde_json::from_value(migration.value).map_err(|e| to_parse_error(e.to_string()));
+    }
+    // Parsing the source again keeps the positions of errors in the model
+    parse_source(source, format)
+}
+
+// Project files included by a stack contain a single project
+pub fn parse_project_config(source: &str, format: StackFormat) -> Result<ProjectConfig, StackParseError> {
+    parse_source(source, format)
+}
+
+// Reads a stack source without the model, for files that don't match it yet such as legacy stacks
+pub fn parse_stack_value(source: &str, format: StackFormat) -> Result<Value, StackParseError> {
+    parse_source(source, format)
+}
+
+// Reads a stack source without the model but upgraded to the current schema version, for files that only hold
+// part of a stack and leave out what they get from the files they extend or include
+pub fn parse_partial_stack_value(source: &str, format: StackFormat) -> Result<Value, StackParseError> {
+    let value: Value = parse_source(source, format)?;
+    let to_parse_error = |message: String| StackParseError {
+        message,
+        line: None,
+        column: None,
+    };
+    if migration_util::check_stack_version(&value).map_err(to_parse_error)? < migration_util::CURRENT_SCHEMA_VERSION {
+        return Ok(migration_util::upgrade_stack(&value).map_err(to_parse_error)?.value);
+    }
+    Ok(value)
+}
+
+fn parse_source<T: DeserializeOwned>(source: &str, format: StackFormat) -> Result<T, StackParseError> {
+    match format {
+        // Comments and trailing commas are accepted in JSON, stripping them keeps error positions intact
+        StackFormat::Json => serde_json::from_str(&jsonc_util::strip_comments(source)).map_err(|e| StackParseError {
+            message: e.to_string(),
+            line: Some(e.line()).filter(|line| *line > 0),
+            column: Some(e.column()).filter(|_| e.line() > 0),
+        }),
+        StackFormat::Json5 => json5::from_str(source).map_err(|e| {
+            let json5::Error::Message { msg, location } = e;
+            StackParseError {
+                message: msg,
+                line: location.as_ref().map(|location| location.line),
+                column: location.as_ref().map(|location| location.column),
+            }
+        }),
+        StackFormat::Yaml => serde_yaml::from_str(source).map_err(|e| StackParseError {
+            message: e.to_string(),
+            line: e.location().map(|location| location.line()),
+            column: e.location().map(|location| location.column()),
+        }),
+        StackFormat::Toml => toml::from_str(source).map_err(|e| {
+            let position = e.span().map(|span| get_line_and_column(source, span.start));
+            StackParseError {
+                message: e.message().to_string(),
+                line: position.map(|(line, _)| line),
+                column: position.map(|(_, column)| column),
+            }
+        }),
+    }
+}
+
+fn get_line_and_column(source: &str, offset: usize) -> (usize, usize) {
+    let before = &source[..offset.min(source.len())];
+    let line = before.matches('\n').count() + 1;
+    let column = before.chars().rev().take_while(|c| *c != '\n').count() + 1;
+    (line, column)
+}
+
+// Reads a stack file in the format of its extension
+pub fn read_stack_config(stack_file: &str) -> Result<StackConfig, String> {
+    let source = match std::fs::read_to_string(stack_file) {
+        Ok(source) => source,
+        Err(e) => return Err(format!("Could not open and read stack file: {} {}", stack_file, e)),
+    };
+    parse_stack_config(&source, get_stack_format(stack_file))
+        .map_err(|e| format!("Could not parse stack file: {} {}", stack_file, e.message))
+}
+
+// JSON files are rewritten in place of the existing source so its comments and key order survive
+pub fn serialize_stack_config(config: &StackConfig, format: StackFormat, existing: Option<&str>) -> Result<String, String> {
+    let value = serde_json::to_value(config).map_err(|e| e.to_string())?;
+    serialize_stack_value(value, format, existing, KeyOrder::Keep)
+}
+
+// Rewrites a stack source in the canonical key order of the model with pretty indentation, keeping the
+// comments of JSON files. Optional values that are equal to their defaults can be left out. Partial files keep
+// their key order and every value, a default they set still overrides the value they inherit.
+pub fn format_stack_source(
+    source: &str,
+    format: StackFormat,
+    omit_defaults: bool,
+    partial: bool,
+) -> Result<String, StackParseError> {
+    if partial {
+        let value = parse_partial_stack_value(source, format)?;
+        return serialize_stack_value(value, format, Some(source), KeyOrder::Keep).map_err(|message| StackParseError {
+            message,
+            line: None,
+            column: None,
+        });
+    }
+    let config = parse_stack_config(source, format)?;
+    let mut value = serde_json::to_value(&config).map_err(|e| StackParseError {
+        message: e.to_string(),
+        line: None,
+        column: None,
+    })?;
+    if omit_defaults {
+        schema_util::remove_default_values(&mut value);
+    }
+    serialize_stack_value(value, format, Some(source), KeyOrder::Canonical).map_err(|message| StackParseError {
+        message,
+        line: None,
+        column: None,
+    })
+}
+
+// YAML and TOML files are rewritten without their comments, apart from the comment lines at the top. Returns the
+// line number and text of the first comment of the source that is missing from the rewritten one. A "#" only
+// counts as a comment when removing it leaves the parsed file unchanged, so "#" inside strings and block scalars
+// doesn't.
+pub fn find_lost_comment(source: &str, rewritten: &str, format: StackFormat) -> Option<(usize, String)> {
+    if format != StackFormat::Yaml && format != StackFormat::Toml {
+        return None;
+    }
+    let value = parse_stack_value(source, format).ok()?;
+    let lines: Vec<&str> = source.lines().collect();
+    for (index, line) in lines.iter().enumerate() {
+        let start = match find_comment_start(line) {
+            Some(start) => start,
+            None => continue,
+        };
+        let comment = line[start..].trim_end();
+        if rewritten.lines().any(|rewritten_line| rewritten_line.trim_end().ends_with(comment)) {
+            continue;
+        }
+        let mut stripped = lines.clone();
+        stripped[index] = &line[..start];
+        if parse_stack_value(&stripped.join("\n"), format).is_ok_and(|stripped_value| stripped_value == value) {
+            return Some((index + 1, comment.to_string()));
+        }
+    }
+    None
+}
+
+// Where a "#" that starts a comment could be: outside quoted values, at the start of the line or after whitespace
+pub fn find_comment_start(line: &str) -> Option<usize> {
+    let mut quote: Option<char> = None;
+    let mut escaped = false;
+    let mut previous = ' ';
+    for (index, c) in line.char_indices() {
+        match quote {
+            Some('"') if escaped => escaped = false,
+            Some('"') if c == '\\' => escaped = true,
+            Some(open) if c == open => quote = None,
+            Some(_) => {}
+            // A doubled quote inside single quotes is a quote, which reopens the value here
+            None if c == '\'' && previous == '\'' => quote = Some(c),
+            None if (c == '"' || c == '\'') && (previous.is_whitespace() || ":[{,=".contains(previous)) => {
+                quote = Some(c)
+            }
+            None if c == '#' && previous.is_whitespace() => return Some(index),
+            None => {}
+        }
+        previous = c;
+    }
+    None
+}
+
+// YAML and TOML have no "$schema" key, editors read the schema from a comment on the first line instead.
+// Without one, the comment lines at the top of the existing file are kept.
+pub fn serialize_stack_value(
+    mut value: Value,
+    format: StackFormat,
+    existing: Option<&str>,
+    key_order: KeyOrder,
+) -> Result<String, String> {
+    if format == StackFormat::Json || format == StackFormat::Json5 {
+        return Ok(jsonc_util::update_source(existing, &value, key_order));
+    }
+    let schema = value.get("$schema").and_then(Value::as_str).map(|schema| schema.to_string());
+    if let Some(values) = value.as_object_mut() {
+        values.retain(|key, _| key != "$schema");
+    }
+    let header = match schema {
+        Some(schema) if format == StackFormat::Yaml => format!("# yaml-language-server: $schema={}\n", schema),
+        Some(schema) => format!("#:schema {}\n", schema),
+        None => existing
+            .unwrap_or_default()
+            .lines()
+            .take_while(|line| line.starts_with('#'))
+            .map(|line| format!("{}\n", line))
+            .collect(),
+    };
+    let content = match format {
+        StackFormat::Yaml => serde_yaml::to_string(&value).map_err(|e| e.to_string())?,
+        _ => toml::to_string(&value).map_err(|e| e.to_string())?,
+    };
+    Ok(header + &content)
+}
@@ -0,0 +1,317 @@
+use clap::ValueEnum;
+use colored::Colorize;
+use serde_json::{json, Value};
+
+use crate::models::stack_config_json::{CommandConfig, ProjectConfig, ScriptConfig, StackConfig, StageConfig};
+use crate::utils::{configuration_util, ldot_stack_util, param_util, stage_graph_util};
+use crate::utils::stage_graph_util::StageReference;
+
+// What "ldot list" prints, one line per entry
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ListKind {
+    Stacks,
+    Projects,
+    Stages,
+    Scripts,
+}
+
+// One entry of "ldot list". Projects, stages and scripts are named "stack/project/stage" the way "ldot show" takes them.
+pub struct ListEntry {
+    pub name: String,
+    pub description: String,
+    pub details: Value,
+}
+
+// What "ldot show" prints: a whole stack, one of its projects or one stage, e.g. "stack/api/start"
+pub struct ShowTarget {
+    pub stack: String,
+    pub project: Option<String>,
+    pub stage: Option<String>,
+}
+
+pub fn parse_show_target(target: &str) -> Result<ShowTarget, String> {
+    let parts: Vec<&str> = target.split('/').collect();
+    if parts.len() > 3 || parts.iter().any(|part| part.is_empty()) {
+        return Err(format!("Expected <stack>[/<project>[/<stage>]]: {}", target));
+    }
+    Ok(ShowTarget {
+        stack: parts[0].to_string(),
+        project: parts.get(1).map(|project| project.to_string()),
+        stage: parts.get(2).map(|stage| stage.to_string()),
+    })
+}
+
+// Every registered stack as it is executed, or only the given one. Stacks that can't be read are reported and
+// left out so one broken file doesn't hide the others.
+fn read_registered_stacks(stack: Option<&str>, profile: Option<String>) -> Result<Vec<(String, StackConfig)>, String> {
+    let stack_files = match stack {
+        Some(stack) => vec![configuration_util::get_ldot_stack_from_stack_name(stack)?],
+        None => configuration_util::get_configuration()
+            .map_err(|e| format!("Could not fetch LDOT configuration: {}", e))?
+            .registered_stack_files,
+    };
+    let mut stacks: Vec<(String, StackConfig)> = vec![];
+    for stack_file in stack_files {
+        match ldot_stack_util::read_ldot_stack_config(&stack_file, profile.clone()) {
+            Ok(stack_config) => stacks.push((stack_file, stack_config)),
+            Err(e) => eprintln!("{}", e),
+        }
+    }
+    Ok(stacks)
+}
+
+// Lists the registered stacks, or the projects, stages or scripts of every registered stack or only of the given one
+pub fn list(kind: ListKind, stack: Option<&str>, profile: Option<String>) -> Result<Vec<ListEntry>, String> {
+    let mut entries: Vec<ListEntry> = vec![];
+    for (stack_file, stack_config) in read_registered_stacks(stack, profile)? {
+        let stack_name = &stack_config.stack_name;
+        match kind {
+            ListKind::Stacks => entries.push(ListEntry {
+                name: stack_name.to_string(),
+                description: stack_config.description.to_string(),
+                details: json!({
+                    "stack_name": stack_name,
+                    "description": stack_config.description,
+                    "file": stack_file,
+                }),
+            }),
+            ListKind::Projects => {
+                for project in stack_config.projects.iter().filter(|project| !project.group) {
+                    entries.push(ListEntry {
+                        name: format!("{}/{}", stack_name, project.project_name),
+                        description: project.project_description.to_string(),
+                        details: json!({
+                            "stack_name": stack_name,
+                            "project_name": project.project_name,
+                            "project_description": project.project_description,
+                            "tags": project.tags,
+                        }),
+                    });
+                }
+            }
+            ListKind::Stages => {
+                for project in stack_config.projects.iter().filter(|project| !project.group) {
+                    for stage in &project.stages {
+                        entries.push(ListEntry {
+                            name: format!("{}/{}/{}", stack_name, project.project_name, stage.stage_name),
+                            description: stage.stage_description.to_string(),
+                            details: json!({
+                                "stack_name": stack_name,
+                                "project_name": project.project_name,
+                                "stage_name": stage.stage_name,
+                                "aliases": stage.aliases,
+                                "stage_description": stage.stage_description,
+                            }),
+                        });
+                    }
+                }
+            }
+            ListKind::Scripts => {
+                for script in &stack_config.scripts {
+                    entries.push(ListEntry {
+                        name: format!("{}/{}", stack_name, script.script_name),
+                        description: script.script_description.to_string(),
+                        details: json!({
+                            "stack_name": stack_name,
+                            "script_name": script.script_name,
+                            "script_description": script.script_description,
+                        }),
+                    });
+                }
+            }
+        }
+    }
+    Ok(entries)
+}
+
+// Names in one column and descriptions in the next
+pub fn render_list(entries: &[ListEntry]) -> String {
+    let width = entries.iter().map(|entry| entry.name.len()).max().unwrap_or(0);
+    entries
+        .iter()
+        .map(|entry| match entry.description.is_empty() {
+            true => format!("{}\n", entry.name),
+            false => format!("{:width$}  {}\n", entry.name, entry.description.dimmed(), width = width),
+        })
+        .collect()
+}
+
+// The stack, project or stage a target names with everything below it, as JSON shaped like the stack file
+pub fn show(target: &ShowTarget, profile: Option<String>) -> Result<Value, String> {
+    let (stack_file, stack_config) = match read_registered_stacks(Some(&target.stack), profile)?.pop() {
+        Some(stack) => stack,
+        None => return Err(format!("Could not read stack: {}", target.stack)),
+    };
+    let project = match &target.project {
+        Some(project_name) => match stage_graph_util::find_project(&stack_config, project_name) {
+            Some(project) => project,
+            None => return Err(format!("Project not found: {}", project_name)),
+        },
+        None => {
+            return Ok(json!({
+                "stack_name": stack_config.stack_name,
+                "description": stack_config.description,
+                "file": stack_file,
+                "projects": stack_config
+                    .projects
+                    .iter()
+                    .filter(|project| !project.group)
+                    .map(describe_project)
+                    .collect::<Vec<Value>>(),
+                "scripts": stack_config.scripts.iter().map(describe_script).collect::<Vec<Value>>(),
+            }))
+        }
+    };
+    match &target.stage {
+        Some(stage_name) => {
+            let reference = StageReference {
+                project: project.project_name.to_string(),
+                stage: stage_name.to_string(),
+            };
+            match stage_graph_util::find_stage(&stack_config, &reference) {
+                Some(stage) => Ok(describe_stage(stage)),
+                None => Err(format!("Stage not found: {}", reference)),
+            }
+        }
+        None => Ok(describe_project(project)),
+    }
+}
+
+fn describe_project(project: &ProjectConfig) -> Value {
+    json!({
+        "project_name": project.project_name,
+        "project_description": project.project_description,
+        "tags": project.tags,
+        "stages": project.stages.iter().map(describe_stage).collect::<Vec<Value>>(),
+    })
+}
+
+fn describe_stage(stage: &StageConfig) -> Value {
+    json!({
+        "stage_name": stage.stage_name,
+        "aliases": stage.aliases,
+        "stage_description": stage.stage_description,
+        "prerequisites": stage.prerequisites,
+        "commands": describe_commands(&stage.commands),
+    })
+}
+
+fn describe_script(script: &ScriptConfig) -> Value {
+    let params: Vec<Value> = script
+        .params
+        .iter()
+        .map(|param| {
+            json!({
+                "param_name": param.param_name,
+                "param_description": param.param_description,
+                "param_type": param.param_type,
+                "default": param_util::get_default_value(param).ok().flatten(),
+            })
+        })
+        .collect();
+    json!({
+        "script_name": script.script_name,
+        "script_description": script.script_description,
+        "params": params,
+        "commands": describe_commands(&script.commands),
+    })
+}
+
+fn describe_commands(commands: &[CommandConfig]) -> Vec<String> {
+    commands.iter().map(|command| command.to_details().run).collect()
+}
+
+// Renders what "show" returns as an indented tree, e.g.
+//   api  Backend API
+//     start  Starts the API
+//       prerequisites: db:start
+//       > cargo run
+pub fn render_tree(value: &Value) -> String {
+    let mut lines: Vec<String> = vec![];
+    if value.get("stack_name").is_some() {
+        lines.push(format_name(value, "stack_name", "description"));
+        lines.push(format!("  file: {}", get_str(value, "file")));
+        lines.push("  projects:".to_string());
+        for project in get_array(value, "projects") {
+            render_project(project, 2, &mut lines);
+        }
+        lines.push("  scripts:".to_string());
+        for script in get_array(value, "scripts") {
+            render_script(script, 2, &mut lines);
+        }
+    } else if value.get("project_name").is_some() {
+        render_project(value, 0, &mut lines);
+    } else {
+        render_stage(value, 0, &mut lines);
+    }
+    lines.iter().map(|line| format!("{}\n", line)).collect()
+}
+
+fn render_project(project: &Value, depth: usize, lines: &mut Vec<String>) {
+    let indent = "  ".repeat(depth);
+    lines.push(format!("{}{}", indent, format_name(project, "project_name", "project_description")));
+    let tags = get_strings(project, "tags");
+    if !tags.is_empty() {
+        lines.push(format!("{}  tags: {}", indent, tags.join(", ")));
+    }
+    for stage in get_array(project, "stages") {
+        render_stage(stage, depth + 1, lines);
+    }
+}
+
+fn render_stage(stage: &Value, depth: usize, lines: &mut Vec<String>) {
+    let indent = "  ".repeat(depth);
+    lines.push(format!("{}{}", indent, format_name(stage, "stage_name", "stage_description")));
+    let aliases = get_strings(stage, "aliases");
+    if !aliases.is_empty() {
+        lines.push(format!("{}  aliases: {}", indent, aliases.join(", ")));
+    }
+    let prerequisites = get_strings(stage, "prerequisites");
+    if !prerequisites.is_empty() {
+        lines.push(format!("{}  prerequisites: {}", indent, prerequisites.join(", ")));
+    }
+    for command in get_strings(stage, "commands") {
+        lines.push(format!("{}  > {}", indent, command.purple()));
+    }
+}
+
+fn render_script(script: &Value, depth: usize, lines: &mut Vec<String>) {
+    let indent = "  ".repeat(depth);
+    lines.push(format!("{}{}", indent, format_name(script, "script_name", "script_description")));
+    for param in get_array(script, "params") {
+        let default = match param.get("default").and_then(Value::as_str) {
+            Some(default) => format!("default {}", default),
+            None => "required".to_string(),
+        };
+        lines.push(format!(
+            "{}  param {} ({}, {})",
+            indent,
+            format_name(param, "param_name", "param_description"),
+            get_str(param, "param_type"),
+            default
+        ));
+    }
+    for command in get_strings(script, "commands") {
+        lines.push(format!("{}  > {}", indent, command.purple()));
+    }
+}
+
+fn format_name(value: &Value, name_key: &str, description_key: &str) -> String {
+    let name = get_str(value, name_key).bold();
+    match get_str(value, description_key) {
+        "" => name.to_string(),
+        description => format!("{}  {}", name, description.dimmed()),
+    }
+}
+
+fn get_str<'a>(value: &'a Value, key: &str) -> &'a str {
+    value.get(key).and_then(Value::as_str).unwrap_or_default()
+}
+
+fn get_array<'a>(value: &'a Value, key: &str) -> &'a [Value] {
+    value.get(key).and_then(Value::as_array).map(Vec::as_slice).unwrap_or_default()
+}
+
+fn get_strings<'a>(value: &'a Value, key: &str) -> Vec<&'a str> {
+    get_array(value, key).iter().filter_map(Value::as_str).collect()
+}
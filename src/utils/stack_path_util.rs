@@ -0,0 +1,42 @@
+use std::path::{Path, PathBuf};
+
+use crate::models::stack_config_json::{ProjectConfig, StackConfig};
+
+// Directory containing the registered stack file, every relative path in the stack is anchored here.
+pub fn get_stack_directory(stack_file: &str) -> PathBuf {
+    match Path::new(stack_file).parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent.to_path_buf(),
+        _ => PathBuf::from("."),
+    }
+}
+
+// The stack's "root", relative to the stack file. Defaults to the stack file's directory.
+pub fn get_root_directory(stack_config: &StackConfig, stack_file: &str) -> PathBuf {
+    let stack_directory = get_stack_directory(stack_file);
+    match &stack_config.root {
+        Some(root) => stack_directory.join(root),
+        None => stack_directory,
+    }
+}
+
+// The project's "path", relative to the stack root. Defaults to the stack root.
+pub fn get_project_directory(stack_config: &StackConfig, stack_file: &str, project: &ProjectConfig) -> PathBuf {
+    let root_directory = get_root_directory(stack_config, stack_file);
+    match &project.path {
+        Some(path) => root_directory.join(path),
+        None => root_directory,
+    }
+}
+
+// The directory commands run in and a relative "cwd" is resolved against: the project directory for stages and
+// the stack root for scripts. Both default to the stack file's directory, wherever ldot is started from.
+pub fn get_default_working_directory(
+    stack_config: &StackConfig,
+    stack_file: &str,
+    project: Option<&ProjectConfig>,
+) -> PathBuf {
+    match project {
+        Some(project) => get_project_directory(stack_config, stack_file, project),
+        None => get_root_directory(stack_config, stack_file),
+    }
+}
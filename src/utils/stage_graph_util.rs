@@ -0,0 +1,427 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+
+use crate::models::stack_config_json::{ProjectConfig, StackConfig, StageConfig};
+use crate::utils::generic_utils;
+
+// A fully qualified reference to a stage, written as "project:stage" in stack files.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct StageReference {
+    pub project: String,
+    pub stage: String,
+}
+
+impl fmt::Display for StageReference {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.project, self.stage)
+    }
+}
+
+// The projects to run a stage in: every project, projects with one of the tags, or projects whose name matches
+// a pattern such as "svc-*". A name without "*" or "?" selects exactly that project. Groups are only selected by
+// their exact name, so "--all" and patterns don't run their members a second time.
+#[derive(Debug, Clone)]
+pub enum ProjectSelector {
+    All,
+    Tags(Vec<String>),
+    Name(String),
+}
+
+impl fmt::Display for ProjectSelector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProjectSelector::All => write!(f, "all"),
+            ProjectSelector::Tags(tags) => write!(f, "tagged {}", tags.join(", ")),
+            ProjectSelector::Name(name) if self.is_single_project() => write!(f, "{}", name),
+            ProjectSelector::Name(pattern) => write!(f, "matching {}", pattern),
+        }
+    }
+}
+
+impl ProjectSelector {
+    pub fn matches(&self, project: &ProjectConfig) -> bool {
+        match self {
+            ProjectSelector::Name(name) if project.group => *name == project.project_name,
+            _ if project.group => false,
+            ProjectSelector::All => true,
+            ProjectSelector::Tags(tags) => project.tags.iter().any(|tag| tags.contains(tag)),
+            ProjectSelector::Name(pattern) => generic_utils::matches_glob(pattern, &project.project_name),
+        }
+    }
+
+    pub fn is_single_project(&self) -> bool {
+        matches!(self, ProjectSelector::Name(name) if !name.contains(['*', '?']))
+    }
+}
+
+#[derive(PartialEq)]
+enum VisitState {
+    Visiting,
+    Visited,
+}
+
+// Parses a prerequisite such as "project1:start". A bare "start" refers to a stage of the current project.
+pub fn parse_stage_reference(reference: &str, current_project: &str) -> Result<StageReference, String> {
+    let parts: Vec<&str> = reference.split(':').collect();
+    let (project, stage) = match parts.as_slice() {
+        [stage] => (current_project, *stage),
+        [project, stage] => (*project, *stage),
+        _ => {
+            return Err(format!(
+                "Invalid prerequisite, expected \"project:stage\": {}",
+                reference
+            ))
+        }
+    };
+    if project.is_empty() || stage.is_empty() {
+        return Err(format!(
+            "Invalid prerequisite, expected \"project:stage\": {}",
+            reference
+        ));
+    }
+    Ok(StageReference {
+        project: project.to_string(),
+        stage: stage.to_string(),
+    })
+}
+
+pub fn find_project<'a>(stack_config: &'a StackConfig, project_name: &str) -> Option<&'a ProjectConfig> {
+    stack_config
+        .projects
+        .iter()
+        .find(|project| project.project_name == project_name)
+}
+
+// Stages are found by their name or one of their aliases
+pub fn find_stage<'a>(stack_config: &'a StackConfig, reference: &StageReference) -> Option<&'a StageConfig> {
+    find_project(stack_config, &reference.project)?
+        .stages
+        .iter()
+        .find(|stage| stage.stage_name == reference.stage || stage.aliases.contains(&reference.stage))
+}
+
+// Replaces an alias with the name of its stage, so "api:fetch" and "api:clone" are the same stage in the graph
+pub fn resolve_stage_reference(stack_config: &StackConfig, reference: &StageReference) -> Option<StageReference> {
+    find_stage(stack_config, reference).map(|stage| StageReference {
+        project: reference.project.to_string(),
+        stage: stage.stage_name.to_string(),
+    })
+}
+
+// Leaves out disabled projects and every prerequisite on their stages, so the rest of the stack runs without them
+pub fn remove_disabled_projects(stack_config: &mut StackConfig) {
+    let disabled: Vec<String> = stack_config
+        .projects
+        .iter()
+        .filter(|project| !project.enabled)
+        .map(|project| project.project_name.to_string())
+        .collect();
+    if disabled.is_empty() {
+        return;
+    }
+    stack_config.projects.retain(|project| project.enabled);
+    for project in &mut stack_config.projects {
+        let project_name = project.project_name.to_string();
+        for stage in &mut project.stages {
+            stage.prerequisites.retain(|prerequisite| {
+                parse_stage_reference(prerequisite, &project_name)
+                    .map_or(true, |reference| !disabled.contains(&reference.project))
+            });
+        }
+    }
+}
+
+// Adds a project for every group, with a stage for each stage name of its members that only has the members' stages
+// of that name as prerequisites. Members are project names or patterns such as "svc-*", groups can't contain groups.
+pub fn expand_groups(stack_config: &mut StackConfig) {
+    let project_count = stack_config.projects.len();
+    for (group_name, members) in stack_config.groups.clone() {
+        let mut stages: Vec<StageConfig> = vec![];
+        for project in &stack_config.projects[..project_count] {
+            if !members.iter().any(|member| generic_utils::matches_glob(member, &project.project_name)) {
+                continue;
+            }
+            for stage in &project.stages {
+                let prerequisite = format!("{}:{}", project.project_name, stage.stage_name);
+                match stages.iter_mut().find(|group_stage| group_stage.stage_name == stage.stage_name) {
+                    Some(group_stage) => group_stage.prerequisites.push(prerequisite),
+                    None => stages.push(StageConfig {
+                        stage_name: stage.stage_name.to_string(),
+                        aliases: vec![],
+                        stage_description: format!("Runs {} in group {}", stage.stage_name, group_name),
+                        prerequisites: vec![prerequisite],
+                        commands: vec![],
+                        shell: None,
+                        exec: None,
+                        continue_on_error: false,
+                        env: BTreeMap::new(),
+                        env_files: vec![],
+                    }),
+                }
+            }
+        }
+        add_group_aliases(&stack_config.projects[..project_count], &members, &mut stages);
+        stack_config.projects.push(ProjectConfig {
+            project_name: group_name,
+            project_description: format!("Group of {}", members.join(", ")),
+            path: None,
+            enabled: true,
+            tags: vec![],
+            env: BTreeMap::new(),
+            env_files: vec![],
+            stages,
+            group: true,
+        });
+    }
+}
+
+// A group stage gets the aliases of its members' stages, unless the alias is the name or alias of another group stage
+fn add_group_aliases(projects: &[ProjectConfig], members: &[String], stages: &mut [StageConfig]) {
+    for project in projects {
+        if !members.iter().any(|member| generic_utils::matches_glob(member, &project.project_name)) {
+            continue;
+        }
+        for stage in &project.stages {
+            for alias in &stage.aliases {
+                let taken = |group_stage: &StageConfig| {
+                    group_stage.stage_name == *alias || group_stage.aliases.contains(alias)
+                };
+                if stages.iter().any(taken) {
+                    continue;
+                }
+                let group_stage = stages.iter_mut().find(|group_stage| group_stage.stage_name == stage.stage_name);
+                if let Some(group_stage) = group_stage {
+                    group_stage.aliases.push(alias.to_string());
+                }
+            }
+        }
+    }
+}
+
+// Returns the stage in every project the selector matches, and the names of the matching projects without it
+pub fn select_stage_targets(
+    stack_config: &StackConfig,
+    selector: &ProjectSelector,
+    stage: &str,
+) -> (Vec<StageReference>, Vec<String>) {
+    let mut targets: Vec<StageReference> = vec![];
+    let mut skipped: Vec<String> = vec![];
+    for project in stack_config.projects.iter().filter(|project| selector.matches(project)) {
+        let reference = StageReference {
+            project: project.project_name.to_string(),
+            stage: stage.to_string(),
+        };
+        match resolve_stage_reference(stack_config, &reference) {
+            Some(reference) => targets.push(reference),
+            None => skipped.push(project.project_name.to_string()),
+        }
+    }
+    (targets, skipped)
+}
+
+// Returns every stage needed to run the targets, dependencies first, each stage exactly once.
+pub fn resolve_execution_order(
+    stack_config: &StackConfig,
+    targets: &[StageReference],
+) -> Result<Vec<StageReference>, String> {
+    let mut states: HashMap<StageReference, VisitState> = HashMap::new();
+    let mut path: Vec<StageReference> = vec![];
+    let mut order: Vec<StageReference> = vec![];
+
+    for target in targets {
+        if find_stage(stack_config, target).is_none() {
+            return Err(format!("Stage not found: {}", target));
+        }
+        visit_stage(stack_config, target, &mut states, &mut path, &mut order)?;
+    }
+    Ok(order)
+}
+
+fn visit_stage(
+    stack_config: &StackConfig,
+    reference: &StageReference,
+    states: &mut HashMap<StageReference, VisitState>,
+    path: &mut Vec<StageReference>,
+    order: &mut Vec<StageReference>,
+) -> Result<(), String> {
+    let reference = &match resolve_stage_reference(stack_config, reference) {
+        Some(reference) => reference,
+        None => {
+            let required_by = path.last().map(|entry| entry.to_string()).unwrap_or_default();
+            return Err(format!(
+                "Prerequisite not found: {} (required by {})",
+                reference, required_by
+            ));
+        }
+    };
+    match states.get(reference) {
+        Some(VisitState::Visited) => return Ok(()),
+        Some(VisitState::Visiting) => {
+            let cycle_start = path.iter().position(|entry| entry == reference).unwrap_or(0);
+            let mut cycle: Vec<String> = path[cycle_start..].iter().map(|entry| entry.to_string()).collect();
+            cycle.push(reference.to_string());
+            return Err(format!("Prerequisite cycle detected: {}", cycle.join(" -> ")));
+        }
+        None => {}
+    }
+
+    let stage = find_stage(stack_config, reference).unwrap();
+
+    states.insert(reference.clone(), VisitState::Visiting);
+    path.push(reference.clone());
+    for prerequisite in &stage.prerequisites {
+        let dependency = parse_stage_reference(prerequisite, &reference.project)?;
+        visit_stage(stack_config, &dependency, states, path, order)?;
+    }
+    path.pop();
+    states.insert(reference.clone(), VisitState::Visited);
+    order.push(reference.clone());
+    Ok(())
+}
+
+// Returns the direct prerequisites of a stage, already qualified with their project and with aliases resolved.
+pub fn get_stage_dependencies(
+    stack_config: &StackConfig,
+    reference: &StageReference,
+) -> Result<Vec<StageReference>, String> {
+    let stage = match find_stage(stack_config, reference) {
+        Some(stage) => stage,
+        None => return Err(format!("Stage not found: {}", reference)),
+    };
+    stage
+        .prerequisites
+        .iter()
+        .map(|prerequisite| {
+            let dependency = parse_stage_reference(prerequisite, &reference.project)?;
+            Ok(resolve_stage_reference(stack_config, &dependency).unwrap_or(dependency))
+        })
+        .collect()
+}
+
+// Returns every prerequisite cycle in the stack once, e.g. [a:x, b:y, a:x]. Prerequisites that are
+// invalid or point at missing stages are left to the caller.
+pub fn find_prerequisite_cycles(stack_config: &StackConfig) -> Vec<Vec<StageReference>> {
+    let mut states: HashMap<StageReference, VisitState> = HashMap::new();
+    let mut cycles: Vec<Vec<StageReference>> = vec![];
+    for project in &stack_config.projects {
+        for stage in &project.stages {
+            let reference = StageReference {
+                project: project.project_name.to_string(),
+                stage: stage.stage_name.to_string(),
+            };
+            find_cycles_from(stack_config, &reference, &mut states, &mut vec![], &mut cycles);
+        }
+    }
+    cycles
+}
+
+fn find_cycles_from(
+    stack_config: &StackConfig,
+    reference: &StageReference,
+    states: &mut HashMap<StageReference, VisitState>,
+    path: &mut Vec<StageReference>,
+    cycles: &mut Vec<Vec<StageReference>>,
+) {
+    let reference = &match resolve_stage_reference(stack_config, reference) {
+        Some(reference) => reference,
+        None => return,
+    };
+    match states.get(reference) {
+        Some(VisitState::Visited) => return,
+        Some(VisitState::Visiting) => {
+            let cycle_start = path.iter().position(|entry| entry == reference).unwrap_or(0);
+            let mut cycle: Vec<StageReference> = path[cycle_start..].to_vec();
+            cycle.push(reference.clone());
+            cycles.push(cycle);
+            return;
+        }
+        None => {}
+    }
+    let stage = find_stage(stack_config, reference).unwrap();
+
+    states.insert(reference.clone(), VisitState::Visiting);
+    path.push(reference.clone());
+    for prerequisite in &stage.prerequisites {
+        if let Ok(dependency) = parse_stage_reference(prerequisite, &reference.project) {
+            find_cycles_from(stack_config, &dependency, states, path, cycles);
+        }
+    }
+    path.pop();
+    states.insert(reference.clone(), VisitState::Visited);
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    // A stage and its prerequisites
+    type Stage<'a> = (&'a str, &'a [&'a str]);
+
+    // A stack of projects given as (project, [stages]), every stage has an alias "<stage>-alias"
+    fn stack(projects: &[(&str, &[Stage])]) -> StackConfig {
+        let projects: Vec<_> = projects
+            .iter()
+            .map(|(project_name, stages)| {
+                let stages: Vec<_> = stages
+                    .iter()
+                    .map(|(stage_name, prerequisites)| {
+                        let aliases = [format!("{}-alias", stage_name)];
+                        json!({ "stage_name": stage_name, "aliases": aliases, "prerequisites": prerequisites })
+                    })
+                    .collect();
+                json!({ "project_name": project_name, "stages": stages })
+            })
+            .collect();
+        serde_json::from_value(json!({ "stack_name": "test", "projects": projects, "scripts": [] })).unwrap()
+    }
+
+    fn reference(name: &str) -> StageReference {
+        parse_stage_reference(name, "").unwrap()
+    }
+
+    fn names(references: &[StageReference]) -> Vec<String> {
+        references.iter().map(|reference| reference.to_string()).collect()
+    }
+
+    #[test]
+    fn prerequisites_run_first() {
+        let stack_config = stack(&[
+            ("api", &[("build", &["db:start"]), ("start", &["build", "web:build-alias"])]),
+            ("db", &[("start", &[])]),
+            ("web", &[("build", &[])]),
+        ]);
+        let order = resolve_execution_order(&stack_config, &[reference("api:start")]).unwrap();
+        assert_eq!(names(&order), vec!["db:start", "api:build", "web:build", "api:start"]);
+    }
+
+    #[test]
+    fn diamond_runs_shared_prerequisite_once() {
+        let stack_config = stack(&[
+            ("app", &[("top", &["left", "right"]), ("left", &["base"]), ("right", &["base"]), ("base", &[])]),
+        ]);
+        let order = resolve_execution_order(&stack_config, &[reference("app:top"), reference("app:left")]).unwrap();
+        assert_eq!(names(&order), vec!["app:base", "app:left", "app:right", "app:top"]);
+    }
+
+    #[test]
+    fn cycle_is_reported_with_its_path() {
+        let stack_config = stack(&[
+            ("a", &[("x", &["b:y"]), ("start", &["x"])]),
+            ("b", &[("y", &["z"]), ("z", &["a:x-alias"])]),
+        ]);
+        let cycles = find_prerequisite_cycles(&stack_config);
+        assert_eq!(cycles.len(), 1);
+        assert_eq!(names(&cycles[0]), vec!["a:x", "b:y", "b:z", "a:x"]);
+        let error = resolve_execution_order(&stack_config, &[reference("a:start")]).unwrap_err();
+        assert_eq!(error, "Prerequisite cycle detected: a:x -> b:y -> b:z -> a:x");
+    }
+
+    #[test]
+    fn missing_prerequisite_is_reported() {
+        let stack_config = stack(&[("a", &[("x", &["b:y"])])]);
+        let error = resolve_execution_order(&stack_config, &[reference("a:x")]).unwrap_err();
+        assert_eq!(error, "Prerequisite not found: b:y (required by a:x)");
+    }
+}